@@ -0,0 +1,16 @@
+; Prints 5 down to 1. count.obj and count.sym are this program in the object
+; and symbol table formats of lc3as (lc3tools) and PennSim, to check that our
+; files match theirs byte for byte. They are still encoded by hand from the LC-3
+; specification; replace them with the output of `lc3as count.asm` (or PennSim's
+; `as count.asm`) and the tests will check against the real tools.
+        .ORIG x3000
+        LD    R1, COUNT
+        LD    R2, ASCII
+LOOP    ADD   R0, R1, R2
+        OUT
+        ADD   R1, R1, #-1
+        BRp   LOOP
+        HALT
+COUNT   .FILL #5
+ASCII   .FILL x30
+        .END
@@ -0,0 +1,13 @@
+;   Service routine to read a character from the keyboard, without a prompt or echo.
+;   GETC (TRAP x20)
+;   Output: R0 is the character read
+                .ORIG   x0400
+Poll            LDI     R0, KBSR    ; Has a character been typed?
+                BRzp    Poll
+                LDI     R0, KBDR
+                RET
+;
+;   Register locations
+KBSR            .FILL   xFE00
+KBDR            .FILL   xFE02
+                .END
@@ -0,0 +1,269 @@
+use crate::error::FileLoadError;
+use crate::file_io::*;
+use crate::linker::ObjectModule;
+use std::path::Path;
+
+/// First line of an archive file.
+pub const ARCHIVE_MAGIC: &str = "!<lc3-arch>";
+
+/// An object file stored in an archive, with the contents of its `.sym` and `.rel` files.
+pub struct ArchiveMember {
+    pub name: String,
+    pub object: Vec<u8>,
+    pub symbols: String,
+    pub relocations: String,
+}
+
+/// A static library of object files, with an index of the symbols they export.
+///
+/// The file starts with `ARCHIVE_MAGIC`, then the index (a count, then one `SYMBOL MEMBER` line
+/// per export), then the members (a count, then for each a `NAME OBJ SYM REL` line giving the
+/// byte lengths of the `.obj`, `.sym` and `.rel` contents which follow it).
+pub struct Archive {
+    pub members: Vec<ArchiveMember>,
+    pub index: Vec<(String, usize)>,
+}
+
+impl Archive {
+    /// Builds an archive from object files, each with its `.sym` and `.rel` files beside it.
+    pub fn from_objects(paths: &[&str]) -> Result<Self, (String, FileLoadError)> {
+        let mut archive = Archive {
+            members: Vec::new(),
+            index: Vec::new(),
+        };
+
+        for path in paths {
+            let read = |file: String| read_bytes_from_file(&file).map_err(|e| (file, e));
+            let text = |file: String| match read_bytes_from_file(&file).map(String::from_utf8) {
+                Ok(Ok(text)) => Ok(text),
+                Ok(Err(_)) => Err((file, FileLoadError::FsReadFailed)),
+                Err(e) => Err((file, e)),
+            };
+
+            let name = match Path::new(path).file_name() {
+                Some(name) => name.to_string_lossy().replace(char::is_whitespace, "_"),
+                None => path.to_string(),
+            };
+            archive.add(ArchiveMember {
+                name,
+                object: read(path.to_string())?,
+                symbols: text(format!("{path}.sym"))?,
+                relocations: text(format!("{path}.rel"))?,
+            })
+            .map_err(|e| (path.to_string(), e))?;
+        }
+        Ok(archive)
+    }
+
+    fn add(&mut self, member: ArchiveMember) -> Result<(), FileLoadError> {
+        let symbols = parse_symbols(&member.symbols)?;
+        for symbol in symbols {
+            if symbol.status.is_export() {
+                self.index.push((symbol.name, self.members.len()));
+            }
+        }
+        self.members.push(member);
+        Ok(())
+    }
+
+    pub fn read(path: &str) -> Result<Self, FileLoadError> {
+        Self::from_bytes(&read_bytes_from_file(path)?)
+    }
+
+    pub fn write(&self, path: &str) -> Result<usize, FileLoadError> {
+        write_bytes_to_file(path, &self.to_bytes())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = format!("{ARCHIVE_MAGIC}\n{}\n", self.index.len()).into_bytes();
+        for (symbol, member) in &self.index {
+            bytes.extend(format!("{symbol} {member}\n").as_bytes());
+        }
+        bytes.extend(format!("{}\n", self.members.len()).as_bytes());
+        for member in &self.members {
+            bytes.extend(
+                format!(
+                    "{} {} {} {}\n",
+                    member.name,
+                    member.object.len(),
+                    member.symbols.len(),
+                    member.relocations.len()
+                )
+                .as_bytes(),
+            );
+            bytes.extend(&member.object);
+            bytes.extend(member.symbols.as_bytes());
+            bytes.extend(member.relocations.as_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FileLoadError> {
+        let mut reader = ArchiveReader { bytes, pos: 0 };
+        if reader.line()? != [ARCHIVE_MAGIC] {
+            return Err(FileLoadError::InvalidArchive);
+        }
+
+        let mut index = Vec::new();
+        for _ in 0..reader.count()? {
+            match reader.line()?.as_slice() {
+                [symbol, member] => index.push((symbol.to_string(), parse_number(member)?)),
+                _ => return Err(FileLoadError::InvalidArchive),
+            }
+        }
+
+        let mut members = Vec::new();
+        for _ in 0..reader.count()? {
+            let (name, lengths) = match reader.line()?.as_slice() {
+                [name, obj, sym, rel] => (
+                    name.to_string(),
+                    [parse_number(obj)?, parse_number(sym)?, parse_number(rel)?],
+                ),
+                _ => return Err(FileLoadError::InvalidArchive),
+            };
+            let object = reader.take(lengths[0])?.to_vec();
+            let symbols = reader.text(lengths[1])?;
+            let relocations = reader.text(lengths[2])?;
+            members.push(ArchiveMember {
+                name,
+                object,
+                symbols,
+                relocations,
+            });
+        }
+
+        if index.iter().any(|(_, member)| *member >= members.len()) {
+            return Err(FileLoadError::InvalidArchive);
+        }
+        Ok(Self { members, index })
+    }
+
+    /// The member which exports `symbol`, if any.
+    pub fn find(&self, symbol: &str) -> Option<usize> {
+        self.index
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(symbol))
+            .map(|(_, member)| *member)
+    }
+
+    /// Reads a member for linking, named e.g. `libstd.lib(print.obj)`.
+    pub fn module(&self, archive_name: &str, member: usize) -> Result<ObjectModule, FileLoadError> {
+        let member = &self.members[member];
+        Ok(ObjectModule {
+            name: format!("{archive_name}({})", member.name),
+            image: image_from_bytes(&member.object, Endian::detect(&member.object))?,
+            symbols: parse_symbols(&member.symbols)?,
+            relocations: parse_relocations(&member.relocations)?,
+        })
+    }
+}
+
+fn parse_number(text: &str) -> Result<usize, FileLoadError> {
+    text.parse().map_err(|_| FileLoadError::InvalidArchive)
+}
+
+struct ArchiveReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ArchiveReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], FileLoadError> {
+        match self.bytes.get(self.pos..self.pos + len) {
+            Some(bytes) => {
+                self.pos += len;
+                Ok(bytes)
+            }
+            None => Err(FileLoadError::InvalidArchive),
+        }
+    }
+
+    fn text(&mut self, len: usize) -> Result<String, FileLoadError> {
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| FileLoadError::InvalidArchive)
+    }
+
+    /// The whitespace separated fields of the next line.
+    fn line(&mut self) -> Result<Vec<&'a str>, FileLoadError> {
+        let len = match self.bytes[self.pos.min(self.bytes.len())..]
+            .iter()
+            .position(|b| *b == b'\n')
+        {
+            Some(len) => len,
+            None => return Err(FileLoadError::InvalidArchive),
+        };
+        let line = std::str::from_utf8(self.take(len)?).map_err(|_| FileLoadError::InvalidArchive)?;
+        self.pos += 1;
+        Ok(line.split_whitespace().collect())
+    }
+
+    fn count(&mut self) -> Result<usize, FileLoadError> {
+        match self.line()?.as_slice() {
+            [count] => parse_number(count),
+            _ => Err(FileLoadError::InvalidArchive),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assemble::Assembler;
+    use crate::linker::*;
+
+    /// Assembles `source` into `dir/name`, writing the `.obj`, `.sym` and `.rel` files.
+    fn assemble_to(dir: &Path, name: &str, source: &str) -> String {
+        let path = dir.join(name).to_string_lossy().to_string();
+        let mut asm = Assembler::new(&path);
+        asm.relocatable = true;
+        asm.load_str(source);
+        let img = match asm.assemble(vec![]) {
+            Ok(img) => img,
+            Err(errors) => panic!("{errors:?}"),
+        };
+        write_binary_to_file(&path, &img, Endian::Big, false).unwrap();
+        write_symbols_to_file(&format!("{path}.sym"), &img).unwrap();
+        write_relocations_to_file(&format!("{path}.rel"), &img).unwrap();
+        path
+    }
+
+    #[test]
+    pub fn pulls_only_needed_members() {
+        let dir = std::env::temp_dir().join(format!("lc3-archive-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let main = assemble_to(&dir, "main.obj", ".ORIG x3000\nONE .IMPORT\nJSR ONE\nHALT\n.END");
+        let one = assemble_to(&dir, "one.obj", ".ORIG x3100\nTWO .IMPORT\nONE .EXPORT JSR TWO\nRET\n.END");
+        let two = assemble_to(&dir, "two.obj", ".ORIG x3200\nTWO .EXPORT RET\n.END");
+        let unused = assemble_to(&dir, "unused.obj", ".ORIG x3000\nTHREE .EXPORT RET\n.END");
+
+        let archive = Archive::from_objects(&[&one, &two, &unused]).unwrap();
+        let lib = dir.join("test.lib").to_string_lossy().to_string();
+        archive.write(&lib).unwrap();
+        let archive = Archive::read(&lib).unwrap();
+        assert_eq!(archive.members.len(), 3);
+        assert_eq!(archive.find("TWO"), Some(1));
+        assert_eq!(archive.find("THREE"), Some(2));
+        assert_eq!(archive.find("MISSING"), None);
+
+        let mut modules = vec![ObjectModule::load(&main).unwrap()];
+        pull_archive_members(&mut modules, &[(lib.clone(), archive)]).unwrap();
+        let names: Vec<&str> = modules.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec![main.as_str(), &format!("{lib}(one.obj)"), &format!("{lib}(two.obj)")]);
+        assert!(unresolved_imports(&modules).is_empty());
+
+        // The unused member overlaps main.obj, so linking it in would fail
+        let img = link("prog.obj", modules).unwrap();
+        assert_eq!(img.data[0].value, 0x48FF);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    pub fn invalid_archives() {
+        assert!(Archive::from_bytes(b"").is_err());
+        assert!(Archive::from_bytes(b"!<arch>\n0\n0\n").is_err());
+        assert!(Archive::from_bytes(b"!<lc3-arch>\n1\nF 0\n0\n").is_err());
+        assert!(Archive::from_bytes(b"!<lc3-arch>\n0\n1\nm.obj 4 0 0\nab").is_err());
+        assert!(Archive::from_bytes(b"!<lc3-arch>\n0\n0\n").is_ok());
+    }
+}
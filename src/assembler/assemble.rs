@@ -5,19 +5,75 @@ use crate::error::FileLoadError;
 use crate::file_io::*;
 use crate::tokenizer::*;
 use crate::virtual_machine;
+use crate::linker::check_import;
+use crate::console_backend::default_console;
 use core::panic;
 use io::BufRead;
 use std::fs::File;
 use std::io;
 use std::io::BufReader;
-use std::thread;
-use std::time;
 
 #[derive(Clone, Debug)]
 pub enum SymbolStatus {
     Private = 0,
     Export,
     Import,
+    /// Exported with `.WEAK`: used unless another module exports the name with `.EXPORT`.
+    Weak,
+}
+
+impl SymbolStatus {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Private => "private",
+            Self::Export => "export",
+            Self::Import => "import",
+            Self::Weak => "weak",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "private" => Some(Self::Private),
+            "export" => Some(Self::Export),
+            "import" => Some(Self::Import),
+            "weak" => Some(Self::Weak),
+            _ => None,
+        }
+    }
+
+    /// True for `.EXPORT` and `.WEAK` symbols, which other modules can import.
+    pub fn is_export(&self) -> bool {
+        matches!(self, Self::Export | Self::Weak)
+    }
+}
+
+/// What a label marks: an instruction, data (`.FILL`, `.BLKW`, `.STRINGZ`), or a constant
+/// generated by the assembler in a literal pool.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SymbolKind {
+    Code,
+    Data,
+    Constant,
+}
+
+impl SymbolKind {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Code => "code",
+            Self::Data => "data",
+            Self::Constant => "constant",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "code" => Some(Self::Code),
+            "data" => Some(Self::Data),
+            "constant" => Some(Self::Constant),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -28,10 +84,11 @@ pub struct Symbol {
     pub src_ln_number: u16,
     pub size_in_words: u16,
     pub status: SymbolStatus,
+    pub kind: SymbolKind,
 }
 
 pub struct TrapInstruction {
-    pub instructions: Vec<u16>,
+    pub instructions: Vec<(u16, u16)>,
     pub origin: u16,
     pub trap_vector: u16,
     pub memory_writes: Vec<(u16, u16)>,
@@ -115,6 +172,7 @@ pub struct TokenizedLine {
     pub rel_addr: u16,
     pub src_ln_number: u16,
     pub tokens: Vec<Token>,
+    pub generated: bool, // Emitted by the assembler, e.g. a .PROC prologue
 }
 
 pub struct MemoryWrite {
@@ -122,12 +180,157 @@ pub struct MemoryWrite {
     pub value: u16,
 }
 
+/// The words of an image by address. Addresses which nothing writes are gaps, e.g. between
+/// modules linked at different origins, and are left out of formats which can express them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SparseImage {
+    words: BTreeMap<u16, u16>,
+}
+
+impl SparseImage {
+    /// The words of `segments`, e.g. as read from a file.
+    pub fn from_segments(segments: &[ExecutableImageIn]) -> Self {
+        let mut image = Self::default();
+        for segment in segments {
+            for (i, value) in segment.data.iter().enumerate() {
+                image.insert(segment.origin.wrapping_add(i as u16), *value);
+            }
+        }
+        image
+    }
+
+    /// Writes `value` at `addr`, returning the word it replaced, if any.
+    pub fn insert(&mut self, addr: u16, value: u16) -> Option<u16> {
+        self.words.insert(addr, value)
+    }
+
+    pub fn get(&self, addr: u16) -> Option<u16> {
+        self.words.get(&addr).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// `(address, word)` in address order.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, u16)> + '_ {
+        self.words.iter().map(|(addr, value)| (*addr, *value))
+    }
+
+    /// Each run of consecutive addresses, in address order.
+    pub fn segments(&self) -> Vec<ExecutableImageIn> {
+        let mut segments: Vec<ExecutableImageIn> = Vec::new();
+        for (addr, value) in self.iter() {
+            match segments.last_mut() {
+                Some(segment) if segment.origin as usize + segment.data.len() == addr as usize => {
+                    segment.data.push(value)
+                }
+                _ => segments.push(ExecutableImageIn {
+                    origin: addr,
+                    data: vec![value],
+                }),
+            }
+        }
+        segments
+    }
+
+    /// Every word from `origin` up to the last one written, with the gaps as zero, for formats
+    /// which hold one contiguous block such as `.obj`. Fails with the address of the first word
+    /// which is below `origin`, e.g. one which wrapped around past xFFFF.
+    pub fn contiguous(&self, origin: u16) -> Result<ExecutableImageIn, u16> {
+        if let Some((addr, _)) = self.words.range(..origin).next() {
+            return Err(*addr);
+        }
+        let mut data = Vec::new();
+        for (addr, value) in self.words.range(origin..) {
+            let offset = (addr - origin) as usize;
+            data.resize(offset + 1, 0);
+            data[offset] = *value;
+        }
+        Ok(ExecutableImageIn { origin, data })
+    }
+}
+
+/// How a relocation patches the word it applies to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RelocationKind {
+    /// Bits [8:0] hold `target - (addr + 1)`, e.g. `LD`, `LEA` and `BR`.
+    PcOffset9,
+    /// Bits [10:0] hold `target - (addr + 1)`, i.e. `JSR`.
+    PcOffset11,
+    /// The whole word holds the target address, i.e. `.FILL LABEL`.
+    Absolute,
+}
+
+impl RelocationKind {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::PcOffset9 => "PC9",
+            Self::PcOffset11 => "PC11",
+            Self::Absolute => "ABS16",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "PC9" => Some(Self::PcOffset9),
+            "PC11" => Some(Self::PcOffset11),
+            "ABS16" => Some(Self::Absolute),
+            _ => None,
+        }
+    }
+
+    /// Rewrites `word`, stored at `addr`, so that it refers to `target`.
+    pub fn patch(&self, word: u16, addr: u16, target: u16) -> Result<u16, String> {
+        let bits = match self {
+            Self::PcOffset9 => 9,
+            Self::PcOffset11 => 11,
+            Self::Absolute => return Ok(target),
+        };
+        let offset = target.wrapping_sub(addr.wrapping_add(1)) as i16;
+        if offset < -(1 << (bits - 1)) || offset >= 1 << (bits - 1) {
+            return Err(format!(
+                "x{target:04x} is {offset} words away from x{addr:04x}, out of range of a {bits}-bit PC offset."
+            ));
+        }
+        let mask = (1u16 << bits) - 1;
+        Ok((word & !mask) | (offset as u16 & mask))
+    }
+}
+
+/// A reference from the word at `rel_addr` to `symbol`, which is patched when the module is
+/// placed by the linker.
+#[derive(Clone, Debug)]
+pub struct Relocation {
+    pub rel_addr: u16,
+    pub kind: RelocationKind,
+    pub symbol: String,
+}
+
 pub struct ExecutableImageOut {
     pub name: String,
     pub origin: u16,
     pub instructions: Vec<MemoryWrite>,
     pub data: Vec<MemoryWrite>,
     pub symbol_table: Vec<Symbol>,
+    pub listing: Vec<ListingLine>,
+    pub relocations: Vec<Relocation>,
+    /// Where execution starts, if not at the origin.
+    pub entry: Option<u16>,
+}
+
+/// A source line and the words it was assembled into.
+#[derive(Clone, Debug)]
+pub struct ListingLine {
+    pub rel_addr: u16,
+    pub words: Vec<u16>,
+    pub src_ln_number: u16,
+    pub text: String,
+    pub generated: bool,
 }
 
 pub struct ExecutableImageIn{
@@ -146,6 +349,16 @@ impl ExecutableImageIn{
         Ok(Self { origin: binary[0], data})
     }
 
+    /// The image of assembled `writes`, where any word not written is zero.
+    pub fn from_writes(origin: u16, writes: &[MemoryWrite]) -> Self {
+        let mut data = Vec::new();
+        for write in writes {
+            data.resize(data.len().max(write.rel_addr as usize + 1), 0);
+            data[write.rel_addr as usize] = write.value;
+        }
+        Self { origin, data }
+    }
+
     fn range(&self) -> (u16, u16){
         (self.origin, self.origin + self.data.len() as u16 -1)
     }
@@ -183,7 +396,19 @@ impl ExecutableImageOut {
             instructions: Vec::new(),
             data: Vec::new(),
             symbol_table: Vec::new(),
+            listing: Vec::new(),
+            relocations: Vec::new(),
+            entry: None,
+        }
+    }
+
+    /// The instructions and data of the image by address.
+    pub fn sparse(&self) -> SparseImage {
+        let mut image = SparseImage::default();
+        for write in self.instructions.iter().chain(self.data.iter()) {
+            image.insert(self.origin.wrapping_add(write.rel_addr), write.value);
         }
+        image
     }
 
     // fn get_memory_span(&self) -> (u16, u16){
@@ -197,6 +422,31 @@ impl ExecutableImageOut {
     // }
 }
 
+/// The source language accepted by the assembler.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dialect {
+    /// LC-3 with this assembler's extensions: `PUSH!`/`POP!` and the other pseudo-ops,
+    /// `.IMPORT`/`.EXPORT`, `.PROC`, and literal pools.
+    Extended,
+    /// Only the instructions and directives of the LC-3 specification, so the program also
+    /// assembles and runs with the official tools.
+    Strict,
+    /// The extended dialect, also accepting the spellings of lc3tools and PennSim sources:
+    /// `label:`, `.EXTERNAL`, lower case directives and comments right after the operands.
+    Compat,
+}
+
+impl Dialect {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "extended" => Some(Self::Extended),
+            "strict" => Some(Self::Strict),
+            "compat" | "lc3tools" | "pennsim" => Some(Self::Compat),
+            _ => None,
+        }
+    }
+}
+
 pub struct Assembler {
     file_path: String,
     pub raw_lines: Vec<String>,
@@ -206,8 +456,18 @@ pub struct Assembler {
     symbol_table: SymbolTable,
     instruction_set: HashMap<String, InstrDef>,
     pub vm: virtual_machine::VirtualMachine,
+    pub listing: Vec<ListingLine>,
+    relocations: Vec<Relocation>,
+    // (address, label, source line) of each .FILL LABEL
+    address_fills: Vec<(u16, String, u16)>,
+    // Imports which are out of range of the instructions referring to them, reached through veneers
+    far_imports: Vec<String>,
+    /// Leaves imports unresolved (with a zero offset) for the linker, instead of resolving them
+    /// from `.sym` files.
+    pub relocatable: bool,
     pub case_insensitive_labels: bool,
     pub verbose_log: bool,
+    pub dialect: Dialect,
     pub orig: u16,
     end: u16,
 }
@@ -222,8 +482,14 @@ impl Assembler {
             symbol_table: Vec::new(),
             instruction_set: InstructionSet::define_instruction_set(),
             vm: virtual_machine::VirtualMachine::new(),
+            listing: Vec::new(),
+            relocations: Vec::new(),
+            address_fills: Vec::new(),
+            far_imports: Vec::new(),
+            relocatable: false,
             case_insensitive_labels: true,
             verbose_log: false,
+            dialect: Dialect::Extended,
             orig: 0,
             end: 0,
         }
@@ -265,6 +531,12 @@ impl Assembler {
         //println!("Symbol table: {:#?}", self.symbol_table);
     }
 
+    /// Loads source text directly, in place of reading `file_path`.
+    pub fn load_str(&mut self, source: &str) {
+        self.raw_lines = source.lines().map(|l| l.to_string()).collect();
+        self.processed_lines = self.omit_comments();
+    }
+
     pub fn assemble(&mut self, external_files: Vec<&str>) -> Result<ExecutableImageOut, Vec<AsmblrErr>> {
         let mut errors = Vec::new();
         let mut img = ExecutableImageOut::new(self.file_path.clone());
@@ -308,17 +580,49 @@ impl Assembler {
             }
         }
         self.adjust_symbols();
-        if let Err(mut link_errors) = self.resolve_external_symbols(external_files){
-            errors.append(&mut link_errors);
-            return Err(errors);
+        if !self.relocatable {
+            if let Err(mut link_errors) = self.resolve_external_symbols(external_files.clone()){
+                errors.append(&mut link_errors);
+                return Err(errors);
+            }
+
+            // Assemble again, with veneers for the imports which are out of range
+            let far = self.out_of_range_imports();
+            if errors.is_empty() && !far.is_empty() {
+                for (name, src_ln_number) in far {
+                    println!("[ASM]\tLine {src_ln_number}: '{name}' is out of range, reaching it through a veneer.");
+                    self.far_imports.push(name);
+                }
+                self.tokenized_lines.clear();
+                self.symbol_table.clear();
+                self.listing.clear();
+                self.relocations.clear();
+                self.address_fills.clear();
+                return self.assemble(external_files);
+            }
+        }
+        if let Err(mut fill_errors) = self.fill_addresses(&mut img.data) {
+            errors.append(&mut fill_errors);
         }
         //eprintln!("[ASM] WARNING: not resolving external symbols.");
 
         match self.parse_instructions() {
             Ok(instructions) => {
-                for (index, word) in instructions.into_iter().enumerate() {
+                for (addr, word) in instructions {
+                    if self.dialect == Dialect::Strict
+                        && instructions::get_opcode_4bit(word) == OP::RES as u16
+                    {
+                        errors.push(
+                            AsmblrErr::new(
+                                None,
+                                format!("Instruction 0x{word:04x} at x{addr:04x} uses the reserved opcode (RES)."),
+                            )
+                            .compatibility_error()
+                            .clone(),
+                        );
+                    }
                     img.instructions.push(MemoryWrite {
-                        rel_addr: index as u16,
+                        rel_addr: addr - self.orig,
                         value: word,
                     })
                 }
@@ -327,10 +631,28 @@ impl Assembler {
                 errors = [errors, e].concat();
             }
         }
+
+        // Each word a line emits has an address of its own
+        let mut emitted_by: HashMap<u16, u16> = HashMap::new();
+        for ln in &self.listing {
+            for i in 0..ln.words.len() as u16 {
+                let addr = self.orig.wrapping_add(ln.rel_addr).wrapping_add(i);
+                if let Some(other) = emitted_by.insert(addr, ln.src_ln_number) {
+                    errors.push(AsmblrErr::new(
+                        Some(ln.src_ln_number),
+                        format!("x{addr:04x} is written by both line {other} and line {}.", ln.src_ln_number),
+                    ));
+                }
+            }
+        }
         if !errors.is_empty() {
             return Err(errors);
         }
         img.symbol_table = (self.symbol_table).clone();
+        img.listing = self.listing.clone();
+        img.listing.sort_by_key(|ln| ln.rel_addr);
+        img.relocations = self.relocations.clone();
+        img.relocations.sort_by_key(|r| r.rel_addr);
 
         println!("[ASM] assembled {}.", self.file_path);
 
@@ -413,6 +735,13 @@ impl Assembler {
                         format!("Label {} is defined again later.", symbol),
                     ))
                 }
+                let kind = if symbol.starts_with(LITERAL_LABEL_PREFIX) || symbol.starts_with(VENEER_LABEL_PREFIX) {
+                    SymbolKind::Constant
+                } else if Self::emits_data(&tk_ln.tokens) {
+                    SymbolKind::Data
+                } else {
+                    SymbolKind::Code
+                };
                 self.symbol_table.push(Symbol {
                     name: symbol.to_string().clone(),
                     rel_addr: relative_address,
@@ -420,6 +749,7 @@ impl Assembler {
                     src_ln_number: tk_ln.src_ln_number,
                     size_in_words: 1,
                     status: SymbolStatus::Private,
+                    kind,
                 });
                 found_symbol = true;
             }
@@ -453,7 +783,7 @@ impl Assembler {
         let mut tokenized_lines = Vec::new();
         let mut errors = Vec::new();
         for ln in &self.processed_lines {
-            let token_stream = match Token::tokenize_line(ln) {
+            let token_stream = match Token::tokenize_line_in(ln, self.dialect) {
                 Ok(tk) => tk,
                 Err(e) => {
                     errors.push(AsmblrErr::new(
@@ -474,17 +804,520 @@ impl Assembler {
                 rel_addr: ln.number,
                 src_ln_number: ln.actual_line,
                 tokens: token_stream,
+                generated: false,
             });
         }
 
+        if self.dialect == Dialect::Strict {
+            errors.extend(Self::check_strict_compatibility(&tokenized_lines));
+        }
+
         if !errors.is_empty() {
             return Err(errors);
         }
 
+        let tokenized_lines = self.expand_procedures(tokenized_lines)?;
+        let tokenized_lines = self.expand_literal_pools(tokenized_lines)?;
+
         self.tokenized_lines = tokenized_lines.clone();
         Ok(tokenized_lines)
     }
 
+    /// Reports every instruction or directive which is not part of the LC-3 specification.
+    pub fn check_strict_compatibility(lines: &[TokenizedLine]) -> Vec<AsmblrErr> {
+        let mut errors = Vec::new();
+        for ln in lines {
+            for token in &ln.tokens {
+                let msg = match token {
+                    Token::Instruction(instr) if !is_standard_instruction(instr) => format!(
+                        "'{instr}' is an extension to the LC-3 instruction set, and is not accepted by other assemblers."
+                    ),
+                    Token::Directive(dir) if !STANDARD_DIRECTIVES.iter().any(|std| std.eq_ignore_ascii_case(dir)) => format!(
+                        "'.{dir}' is an extension to the LC-3 assembly language, and is not accepted by other assemblers."
+                    ),
+                    _ => continue,
+                };
+                errors.push(AsmblrErr::new(Some(ln.src_ln_number), msg).compatibility_error().clone());
+            }
+        }
+        errors
+    }
+
+    /// Expands `.PROC NAME [.EXPORT] [USES R1, R2, ...]` ... `.ENDP` blocks. The registers listed
+    /// after `USES` are saved with `PUSH!` on entry, and restored with `POP!` (in reverse order)
+    /// before every `RET` inside the procedure.
+    pub fn expand_procedures(
+        &self,
+        lines: Vec<TokenizedLine>,
+    ) -> Result<Vec<TokenizedLine>, Vec<AsmblrErr>> {
+        let mut expanded: Vec<TokenizedLine> = Vec::new();
+        let mut errors = Vec::new();
+
+        // (name, saved registers, line of .PROC)
+        let mut current_proc: Option<(String, Vec<u16>, u16)> = None;
+        // Entry label of a procedure which has no prologue, attached to its first line instead
+        let mut pending_label: Option<Vec<Token>> = None;
+
+        for ln in lines {
+            let generated = |tokens: Vec<Token>| TokenizedLine {
+                rel_addr: ln.rel_addr,
+                src_ln_number: ln.src_ln_number,
+                tokens,
+                generated: true,
+            };
+
+            match ln.tokens.first() {
+                Some(Token::Directive(dir)) if dir == "PROC" => {
+                    if let Some((name, _, src_ln_number)) = &current_proc {
+                        errors.push(AsmblrErr::new(
+                            Some(ln.src_ln_number),
+                            format!(
+                                "Nested .PROC, procedure '{name}' (line {src_ln_number}) has no matching .ENDP."
+                            ),
+                        ));
+                        continue;
+                    }
+
+                    let (entry_label, registers) = match Self::parse_proc_header(&ln.tokens[1..])
+                    {
+                        Ok(header) => header,
+                        Err(msg) => {
+                            errors.push(AsmblrErr::new(Some(ln.src_ln_number), msg));
+                            continue;
+                        }
+                    };
+
+                    if let Some(Token::Label(name)) = entry_label.first() {
+                        current_proc = Some((name.clone(), registers.clone(), ln.src_ln_number));
+                    }
+
+                    if registers.is_empty() {
+                        pending_label = Some(entry_label);
+                        continue;
+                    }
+
+                    for (i, r) in registers.iter().enumerate() {
+                        let mut tokens = if i == 0 { entry_label.clone() } else { vec![] };
+                        tokens.push(Token::Instruction(String::from("PUSH!")));
+                        tokens.push(Token::Register(*r));
+                        expanded.push(generated(tokens));
+                    }
+                    continue;
+                }
+
+                Some(Token::Directive(dir)) if dir == "ENDP" => {
+                    match (&current_proc, ln.tokens.get(1)) {
+                        (None, _) => errors.push(AsmblrErr::new(
+                            Some(ln.src_ln_number),
+                            String::from(".ENDP without a matching .PROC."),
+                        )),
+                        (Some((name, _, _)), Some(Token::Label(end_name)))
+                            if !end_name.eq_ignore_ascii_case(name) =>
+                        {
+                            errors.push(AsmblrErr::new(
+                                Some(ln.src_ln_number),
+                                format!(".ENDP {end_name} does not close procedure '{name}'."),
+                            ))
+                        }
+                        (Some((name, _, src_ln_number)), _) => {
+                            if pending_label.is_some() {
+                                errors.push(AsmblrErr::new(
+                                    Some(*src_ln_number),
+                                    format!("Procedure '{name}' is empty."),
+                                ));
+                            }
+                        }
+                    }
+                    current_proc = None;
+                    pending_label = None;
+                    continue;
+                }
+                _ => {}
+            }
+
+            let mut tokens = ln.tokens.clone();
+            if let Some(entry_label) = pending_label.take() {
+                if tokens.first().is_some_and(|t| t.is(&Token::Label(String::new()))) {
+                    errors.push(AsmblrErr::new(
+                        Some(ln.src_ln_number),
+                        format!(
+                            "Procedure entry '{}' cannot share a line with another label.",
+                            Token::line_to_string(&entry_label)
+                        ),
+                    ));
+                }
+                tokens = [entry_label, tokens].concat();
+            }
+
+            let saved_registers = match &current_proc {
+                Some((_, registers, _)) if !registers.is_empty() => registers,
+                _ => {
+                    expanded.push(TokenizedLine { tokens, ..ln });
+                    continue;
+                }
+            };
+
+            // Split off the label (and .EXPORT/.IMPORT) so it marks the first restoring POP!
+            let body_start = tokens
+                .iter()
+                .position(|t| {
+                    !t.is(&Token::Label(String::new())) && !t.is(&Token::Directive(String::new()))
+                })
+                .unwrap_or(tokens.len());
+            let is_return = matches!(
+                tokens.get(body_start),
+                Some(Token::Instruction(instr)) if instr.eq_ignore_ascii_case("RET")
+            );
+
+            if !is_return {
+                expanded.push(TokenizedLine { tokens, ..ln });
+                continue;
+            }
+
+            let mut label = tokens[..body_start].to_vec();
+            for r in saved_registers.iter().rev() {
+                let mut pop = std::mem::take(&mut label);
+                pop.push(Token::Instruction(String::from("POP!")));
+                pop.push(Token::Register(*r));
+                expanded.push(generated(pop));
+            }
+            expanded.push(TokenizedLine {
+                tokens: tokens[body_start..].to_vec(),
+                ..ln
+            });
+        }
+
+        if let Some((name, _, src_ln_number)) = current_proc {
+            errors.push(AsmblrErr::new(
+                Some(src_ln_number),
+                format!("Procedure '{name}' has no matching .ENDP."),
+            ));
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        if self.verbose_log {
+            for ln in expanded.iter().filter(|ln| ln.generated) {
+                println!("{:02}+\t{}", ln.src_ln_number, Token::line_to_string(&ln.tokens));
+            }
+        }
+
+        Ok(expanded)
+    }
+
+    /// Replaces `LDIMM Rn, <number>` with an `LD` from a literal pool. A pool holds the distinct
+    /// constants loaded since the previous pool, and is placed at the next `.POOL`, or otherwise
+    /// after the last unconditional `BR`, `JMP`, `RET` or `HALT` which keeps it within range of
+    /// its first load. Where there is no such transfer, it is placed behind a `BRnzp` around it.
+    pub fn expand_literal_pools(
+        &self,
+        lines: Vec<TokenizedLine>,
+    ) -> Result<Vec<TokenizedLine>, Vec<AsmblrErr>> {
+        let mut expanded: Vec<TokenizedLine> = Vec::new();
+        let mut errors = Vec::new();
+
+        // (value, label) of each constant or veneer address waiting to be placed
+        let mut pool: Vec<(Token, String)> = Vec::new();
+        let mut pool_count = 0;
+
+        // Words emitted so far, and the word of the first load from the waiting pool
+        let mut words: u16 = 0;
+        let mut first_load: Option<u16> = None;
+        // Index into `expanded` just after the last unconditional transfer since that load
+        let mut last_transfer: Option<usize> = None;
+
+        let pool_lines = |pool: &mut Vec<(Token, String)>, ln: &TokenizedLine| -> Vec<TokenizedLine> {
+            pool.drain(..)
+                .map(|(value, label)| TokenizedLine {
+                    rel_addr: ln.rel_addr,
+                    src_ln_number: ln.src_ln_number,
+                    tokens: vec![
+                        Token::Label(label),
+                        Token::Directive(String::from("FILL")),
+                        value,
+                    ],
+                    generated: true,
+                })
+                .collect()
+        };
+
+        let mut last_line: Option<TokenizedLine> = None;
+        for mut ln in lines {
+            let label_len = ln.tokens.len() - Self::strip_label(&ln.tokens).len();
+            let body_start = match ln.tokens.get(label_len) {
+                Some(Token::Directive(dir)) if dir == "EXPORT" || dir == "WEAK" => label_len + 1,
+                _ => label_len,
+            };
+
+            // The pool has to be placed before this line if it would be out of range after it,
+            // keeping a word for a branch around it
+            let far_import = self.far_import(&ln.tokens[body_start..]);
+            let line_words = match far_import
+                .as_ref()
+                .and_then(|import| Self::veneer(&ln.tokens[body_start..], import))
+            {
+                Some(veneer) => veneer.len() as u16,
+                None => Self::word_count(&ln.tokens),
+            };
+            if let Some(first) = first_load {
+                if words.wrapping_add(line_words).wrapping_sub(first) >= 1 << 8 {
+                    match last_transfer {
+                        Some(index) => {
+                            let placed = pool_lines(&mut pool, &expanded[index - 1]);
+                            words = words.wrapping_add(placed.len() as u16);
+                            expanded.splice(index..index, placed);
+                        }
+                        None => {
+                            let skip = format!("{LITERAL_LABEL_PREFIX}{pool_count}_SKIP");
+                            let generated = |tokens: Vec<Token>| TokenizedLine {
+                                rel_addr: ln.rel_addr,
+                                src_ln_number: ln.src_ln_number,
+                                tokens,
+                                generated: true,
+                            };
+                            expanded.push(generated(vec![
+                                Token::Instruction(String::from("BRNZP")),
+                                Token::Label(skip.clone()),
+                            ]));
+                            let placed = pool_lines(&mut pool, &ln);
+                            words = words.wrapping_add(1 + placed.len() as u16);
+                            expanded.extend(placed);
+                            expanded.push(generated(vec![Token::Label(skip)]));
+                        }
+                    }
+                    pool_count += 1;
+                    first_load = None;
+                    last_transfer = None;
+                }
+            }
+
+            // An out of range import is reached through its address, held in the pool
+            if let Some(import) = far_import {
+                let slot = format!("{VENEER_LABEL_PREFIX}{pool_count}_{import}");
+                if let Some(veneer) = Self::veneer(&ln.tokens[body_start..], &slot) {
+                    if !pool.iter().any(|(_, pooled)| *pooled == slot) {
+                        pool.push((Token::Label(import), slot));
+                    }
+                    first_load = first_load.or(Some(words));
+                    words = words.wrapping_add(line_words);
+                    for (i, tokens) in veneer.into_iter().enumerate() {
+                        let prefix = if i == 0 { &ln.tokens[..body_start] } else { &[] };
+                        expanded.push(TokenizedLine {
+                            rel_addr: ln.rel_addr,
+                            src_ln_number: ln.src_ln_number,
+                            tokens: [prefix, &tokens].concat(),
+                            generated: true,
+                        });
+                    }
+                    last_line = Some(ln);
+                    continue;
+                }
+            }
+
+            match ln.tokens.get(body_start) {
+                Some(Token::Directive(dir)) if dir == "POOL" => {
+                    if ln.tokens.len() > 1 {
+                        errors.push(AsmblrErr::new(
+                            Some(ln.src_ln_number),
+                            String::from(".POOL takes no label or operands."),
+                        ));
+                    }
+                    if !pool.is_empty() {
+                        let placed = pool_lines(&mut pool, &ln);
+                        words = words.wrapping_add(placed.len() as u16);
+                        expanded.extend(placed);
+                        pool_count += 1;
+                        first_load = None;
+                        last_transfer = None;
+                    }
+                    continue;
+                }
+
+                Some(Token::Instruction(instr)) if instr.eq_ignore_ascii_case("LDIMM") => {
+                    let value = match &ln.tokens[body_start + 1..] {
+                        [Token::Register(_), Token::Comma, number] if number.is_number() => {
+                            number.as_u16(None)
+                        }
+                        _ => {
+                            errors.push(AsmblrErr::new(
+                                Some(ln.src_ln_number),
+                                String::from("Expected a register and a number, e.g. 'LDIMM R0, #1234'."),
+                            ));
+                            continue;
+                        }
+                    };
+
+                    let label = format!("{LITERAL_LABEL_PREFIX}{pool_count}_{value:04X}");
+                    if !pool.iter().any(|(_, pooled)| *pooled == label) {
+                        let mut number = NumberLiteral::new();
+                        number.value = value;
+                        number.bits = bits_required_for_number(value);
+                        pool.push((Token::HexLiteral(number), label.clone()));
+                    }
+                    first_load = first_load.or(Some(words));
+                    ln.tokens[body_start] = Token::Instruction(String::from("LD"));
+                    ln.tokens[body_start + 3] = Token::Label(label);
+                    expanded.push(ln.clone());
+                }
+
+                _ => expanded.push(ln.clone()),
+            }
+            words = words.wrapping_add(line_words);
+
+            if !pool.is_empty() && Self::is_unconditional_transfer(&ln.tokens[body_start..]) {
+                last_transfer = Some(expanded.len());
+            }
+            last_line = Some(ln);
+        }
+
+        if !pool.is_empty() {
+            match (last_transfer, last_line) {
+                (Some(index), _) => {
+                    let placed = pool_lines(&mut pool, &expanded[index - 1]);
+                    expanded.splice(index..index, placed);
+                }
+                (None, Some(ln)) => {
+                    let placed = pool_lines(&mut pool, &ln);
+                    expanded.extend(placed);
+                }
+                (None, None) => {}
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(expanded)
+    }
+
+    /// The number of words a line assembles to, e.g. 4 for `MSG .STRINGZ "abc"`.
+    fn word_count(tokens: &[Token]) -> u16 {
+        if Self::emits_instruction(tokens) {
+            return 1;
+        }
+        match Self::strip_export(tokens) {
+            [Token::Directive(dir), Token::StringLiteral(text), ..] if dir == "STRINGZ" => {
+                text.len() as u16 + 1
+            }
+            [Token::Directive(dir), size, ..] if dir == "BLKW" && size.is_number() => size.as_u16(None),
+            [Token::Directive(dir), ..] if dir == "FILL" => 1,
+            _ => 0,
+        }
+    }
+
+    /// The import which `tokens` refers to, if it is out of range and so needs a veneer.
+    fn far_import(&self, tokens: &[Token]) -> Option<String> {
+        tokens.iter().find_map(|token| match token {
+            Token::Label(label)
+                if self.far_imports.iter().any(|far| {
+                    (self.case_insensitive_labels && far.eq_ignore_ascii_case(label)) || far == label
+                }) =>
+            {
+                Some(label.clone())
+            }
+            _ => None,
+        })
+    }
+
+    /// The instructions which take the place of `tokens` to reach an import through `slot`, a
+    /// pool entry holding its address: `JSR X` becomes `LD R7, slot` and `JSRR R7`, `LD`/`ST`
+    /// become `LDI`/`STI`, `LEA` becomes `LD`, and `LDI` is followed by `LDR R, R, #0`.
+    fn veneer(tokens: &[Token], slot: &str) -> Option<Vec<Vec<Token>>> {
+        let instr = |name: &str| Token::Instruction(String::from(name));
+        let slot = Token::Label(slot.to_string());
+        match tokens {
+            [Token::Instruction(op), Token::Label(_)] if op.eq_ignore_ascii_case("JSR") => Some(vec![
+                vec![instr("LD"), Token::Register(7), Token::Comma, slot],
+                vec![instr("JSRR"), Token::Register(7)],
+            ]),
+            [Token::Instruction(op), Token::Register(r), Token::Comma, Token::Label(_)] => {
+                let load = |op: &str| vec![instr(op), Token::Register(*r), Token::Comma, slot.clone()];
+                match op.to_ascii_uppercase().as_str() {
+                    "LD" => Some(vec![load("LDI")]),
+                    "ST" => Some(vec![load("STI")]),
+                    "LEA" => Some(vec![load("LD")]),
+                    "LDI" => Some(vec![
+                        load("LDI"),
+                        vec![
+                            instr("LDR"),
+                            Token::Register(*r),
+                            Token::Comma,
+                            Token::Register(*r),
+                            Token::Comma,
+                            Token::DecimalLiteral(NumberLiteral::new()),
+                        ],
+                    ]),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// True for instructions after which execution never falls through to the next word.
+    fn is_unconditional_transfer(tokens: &[Token]) -> bool {
+        match tokens {
+            [Token::Instruction(instr), ..] => {
+                matches!(
+                    instr.to_ascii_uppercase().as_str(),
+                    "BR" | "BRNZP" | "JMP" | "RET" | "HALT"
+                )
+            }
+            _ => false,
+        }
+    }
+
+    /// Parses `NAME [.EXPORT | .WEAK] [USES R1, R2, ...]`, returning the entry label tokens and the
+    /// registers to save.
+    fn parse_proc_header(tokens: &[Token]) -> Result<(Vec<Token>, Vec<u16>), String> {
+        let mut stream = tokens.iter().peekable();
+        let mut entry_label = match stream.next() {
+            Some(Token::Label(name)) => vec![Token::Label(name.clone())],
+            other => return Err(format!("Expected a procedure name after .PROC, found {other:?}")),
+        };
+
+        if let Some(Token::Directive(dir)) = stream.peek() {
+            if dir != "EXPORT" && dir != "WEAK" {
+                return Err(format!("Unexpected directive '.{dir}' in .PROC, expected .EXPORT or .WEAK"));
+            }
+            entry_label.push(Token::Directive(dir.clone()));
+            stream.next();
+        }
+
+        let mut registers: Vec<u16> = vec![];
+        match stream.next() {
+            None => return Ok((entry_label, registers)),
+            Some(Token::Label(uses)) if uses.eq_ignore_ascii_case("USES") => {}
+            Some(other) => return Err(format!("Expected 'USES' in .PROC, found {other:?}")),
+        }
+
+        let mut expecting_register = true;
+        for token in stream {
+            match (token, expecting_register) {
+                (Token::Register(r), true) => {
+                    if *r == 6 {
+                        return Err(String::from("R6 is the stack pointer and cannot be listed in USES."));
+                    }
+                    if registers.contains(r) {
+                        return Err(format!("R{r} is listed more than once in USES."));
+                    }
+                    registers.push(*r);
+                }
+                (Token::Comma, false) => {}
+                _ => return Err(format!("Expected a list of registers after USES, found {token:?}")),
+            }
+            expecting_register = !expecting_register;
+        }
+
+        if registers.is_empty() || expecting_register {
+            return Err(String::from("Expected a list of registers after USES"));
+        }
+
+        Ok((entry_label, registers))
+    }
+
     pub fn omit_empty_lines(&mut self) {
         //!(tk.0.is_empty() || (tk.0.starts_with(&[Token::Directive(String::new())]))
         let filtered = self
@@ -513,6 +1346,7 @@ impl Assembler {
                 rel_addr: index as u16,
                 src_ln_number: line.src_ln_number,
                 tokens: line.tokens,
+                generated: line.generated,
             });
         }
     }
@@ -646,14 +1480,90 @@ impl Assembler {
         Ok(())
     }
 
+    /// Writes the address of its label into each `.FILL LABEL`, and records it as a relocation.
+    pub fn fill_addresses(&mut self, data: &mut [MemoryWrite]) -> Result<(), Vec<AsmblrErr>> {
+        let mut errors = Vec::new();
+        for (addr, label, src_ln_number) in std::mem::take(&mut self.address_fills) {
+            let symbol = match self.find_symbol(&label) {
+                Some(symbol) => symbol.clone(),
+                None => {
+                    errors.push(AsmblrErr::new(Some(src_ln_number), format!("Undefined label '{label}'")));
+                    continue;
+                }
+            };
+            let value = if self.relocatable && matches!(symbol.status, SymbolStatus::Import) {
+                0
+            } else {
+                symbol.abs_addr
+            };
+            let rel_addr = addr.wrapping_sub(self.orig);
+            for write in data.iter_mut().filter(|w| w.rel_addr == rel_addr) {
+                write.value = value;
+            }
+            for ln in self.listing.iter_mut().filter(|ln| ln.rel_addr == rel_addr) {
+                ln.words[0] = value;
+            }
+            self.relocations.push(Relocation {
+                rel_addr,
+                kind: RelocationKind::Absolute,
+                symbol: symbol.name,
+            });
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(())
+    }
+
+    fn find_symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbol_table.iter().find(|sym| {
+            (self.case_insensitive_labels && sym.name.eq_ignore_ascii_case(name)) || sym.name == name
+        })
+    }
+
+    /// The kind and size declared after `.IMPORT`: `CODE`, `CONSTANT`, or `DATA` with an optional
+    /// size in words (1 by default), e.g. `BUFFER .IMPORT DATA 64`.
+    fn import_annotation(tokens: &[Token]) -> Result<Option<(SymbolKind, u16)>, String> {
+        let kind = match tokens.first() {
+            None => return Ok(None),
+            Some(Token::Label(kind)) => SymbolKind::from_name(&kind.to_ascii_lowercase()),
+            Some(_) => None,
+        };
+        match (kind, &tokens[1..]) {
+            (Some(kind), []) => Ok(Some((kind, 1))),
+            (Some(SymbolKind::Data), [size]) if size.is_number() && size.as_u16(None) > 0 => {
+                Ok(Some((SymbolKind::Data, size.as_u16(None))))
+            }
+            _ => Err(format!(
+                "Expected the kind of the import, e.g. '.IMPORT CODE', '.IMPORT CONSTANT' or '.IMPORT DATA 16', found '{}'.",
+                Token::line_to_string(tokens)
+            )),
+        }
+    }
+
+    /// The relocation for an instruction which refers to a label, e.g. `JSR PRINT`.
+    fn relocation_for(&self, tokens: &[Token], rel_addr: u16) -> Option<Relocation> {
+        let label = tokens.iter().find_map(|token| match token {
+            Token::Label(label) => Some(label),
+            _ => None,
+        })?;
+        let kind = match tokens.first() {
+            Some(Token::Instruction(instr)) if instr.eq_ignore_ascii_case("JSR") => {
+                RelocationKind::PcOffset11
+            }
+            _ => RelocationKind::PcOffset9,
+        };
+        Some(Relocation {
+            rel_addr,
+            kind,
+            symbol: self.find_symbol(label)?.name.clone(),
+        })
+    }
+
     pub fn adjust_symbols(&mut self) {
-        let mut cummulative_offset = 0;
         for symbol in &mut self.symbol_table {
-            symbol.rel_addr += cummulative_offset;
-            if symbol.size_in_words > 1 {
-                cummulative_offset += symbol.size_in_words - 1;
-            }
-            symbol.abs_addr = self.orig + symbol.rel_addr;
+            symbol.abs_addr = self.orig.wrapping_add(symbol.rel_addr);
         }
         if self.verbose_log {
             println!("Adjusted symbol table: {:#?}", self.symbol_table)
@@ -666,6 +1576,7 @@ impl Assembler {
         if self.verbose_log{
             println!("Resolving symbols from external files: {:?}", external_files);
         }
+        let case_insensitive = self.case_insensitive_labels;
         let symbols_to_resolve: Vec<&mut Symbol> = self
             .symbol_table
             .iter_mut()
@@ -673,22 +1584,44 @@ impl Assembler {
             .collect();
         let mut external_tables = Vec::new();
         for path in external_files {
-            let mut external_table = match read_symbols_from_file(path) {
+            let external_table = match read_symbols_from_file(path) {
                 Ok(symbols) => symbols,
                 Err(e) => panic!("{e:?}"),
             };
-            external_tables.append(&mut external_table);
+            external_tables.push((path, external_table));
         }
 
         for internal in symbols_to_resolve {
-            let resolution = match external_tables.iter().find(|external| {
-                matches!(external.status, SymbolStatus::Export) && external.name == internal.name
-            }) {
+            // (file, symbol) of each export of the name; an .EXPORT overrides any .WEAK ones
+            let exports: Vec<(&str, &Symbol)> = external_tables
+                .iter()
+                .flat_map(|(path, table)| table.iter().map(move |external| (*path, external)))
+                .filter(|(_, external)| {
+                    external.status.is_export()
+                        && ((case_insensitive && external.name.eq_ignore_ascii_case(&internal.name))
+                            || external.name == internal.name)
+                })
+                .collect();
+            let strong: Vec<&(&str, &Symbol)> = exports
+                .iter()
+                .filter(|(_, external)| matches!(external.status, SymbolStatus::Export))
+                .collect();
+            if let [(first, _), (second, _), ..] = strong.as_slice() {
+                errors.push(AsmblrErr::new(None, format!("Symbol '{}' is exported by both '{first}' and '{second}'.", internal.name)).link_error().clone());
+                continue;
+            }
+            let resolution = match strong.first().copied().or(exports.first()) {
                 None => {
                     errors.push(AsmblrErr::new(None, format!("Unable to resolve import for symbol '{}'", internal.name)).link_error().clone());
                     continue;
                 }
-                Some(external) => external.abs_addr,
+                Some((path, external)) => {
+                    if let Err(msg) = check_import(internal, &self.file_path, external, path) {
+                        errors.push(AsmblrErr::new(Some(internal.src_ln_number), msg).link_error().clone());
+                        continue;
+                    }
+                    external.abs_addr
+                }
             };
             internal.abs_addr = resolution;
             if resolution > self.orig {
@@ -714,6 +1647,71 @@ impl Assembler {
 
         Ok(())
     }
+    /// Imports which an instruction refers to from further away than its PC offset reaches,
+    /// with the source line of the first such reference, leaving out those which already have
+    /// veneers.
+    fn out_of_range_imports(&self) -> Vec<(String, u16)> {
+        let mut far: Vec<(String, u16)> = Vec::new();
+        for tk_ln in &self.tokenized_lines {
+            let line = Self::strip_label(&tk_ln.tokens);
+            let line = line.strip_prefix(&[Token::Directive(String::new())]).unwrap_or(line);
+            let Some(Token::Instruction(instr)) = line.first() else {
+                continue;
+            };
+            let Some(label) = line.iter().find_map(|token| match token {
+                Token::Label(label) => Some(label),
+                _ => None,
+            }) else {
+                continue;
+            };
+            let Some(symbol) = self.find_symbol(label).filter(|s| matches!(s.status, SymbolStatus::Import)) else {
+                continue;
+            };
+            if far.iter().any(|(name, _)| *name == symbol.name) || self.far_imports.contains(&symbol.name) {
+                continue;
+            }
+
+            let offset_bits = if instr.eq_ignore_ascii_case("JSR") { 11 } else { 9 };
+            let offset = symbol.rel_addr.wrapping_sub(tk_ln.rel_addr.wrapping_add(1)) as i16;
+            if offset < -(1 << (offset_bits - 1)) || offset >= 1 << (offset_bits - 1) {
+                far.push((symbol.name.clone(), tk_ln.src_ln_number));
+            }
+        }
+        far
+    }
+
+    fn strip_label(tokens: &[Token]) -> &[Token] {
+        tokens
+            .strip_prefix(&[Token::Label(String::new())])
+            .unwrap_or(tokens)
+    }
+
+    /// True for lines such as `LABEL .EXPORT ADD R0, R0, #1`, which assemble to one word.
+    fn emits_instruction(tokens: &[Token]) -> bool {
+        let line = Self::strip_label(tokens);
+        let line = line
+            .strip_prefix(&[Token::Directive(String::new())])
+            .unwrap_or(line);
+        line.starts_with(&[Token::Instruction(String::new())])
+    }
+
+    /// Strips the label and any `.EXPORT` or `.WEAK` after it, e.g. `BUFFER .EXPORT .BLKW 16`.
+    fn strip_export(tokens: &[Token]) -> &[Token] {
+        match Self::strip_label(tokens) {
+            [Token::Directive(dir), rest @ ..] if dir == "EXPORT" || dir == "WEAK" => rest,
+            line => line,
+        }
+    }
+
+    /// True for lines starting with `.FILL`, `.STRINGZ` or `.BLKW` (after an optional label and
+    /// `.EXPORT`).
+    fn emits_data(tokens: &[Token]) -> bool {
+        matches!(
+            Self::strip_export(tokens).first(),
+            Some(Token::Directive(dir)) if matches!(dir.as_str(), "FILL" | "STRINGZ" | "BLKW")
+        )
+    }
+
     pub fn parse_directives_to_list(&mut self) -> Result<Vec<(u16, u16)>, Vec<AsmblrErr>> {
         let mut memory_writes = Vec::new();
         let mut reserved_word_count = 0u16;
@@ -721,9 +1719,22 @@ impl Assembler {
 
         let mut errors = Vec::new();
 
+        let mut listing = Vec::new();
+        // (line index, address of the first word emitted by that line)
+        let mut line_addresses: Vec<(u16, u16)> = Vec::new();
+
         for tk_ln in &self.tokenized_lines {
             let line_ = &tk_ln.tokens;
             let line_offset = tk_ln.rel_addr;
+            let writes_before_line = memory_writes.len();
+            line_addresses.push((line_offset, line_offset.wrapping_add(reserved_word_count)));
+
+            // Each line is counted as one word; lines which emit none (e.g. a lone label, or an
+            // .IMPORT) give their word back, and data directives reserve the rest of theirs.
+            if !Self::emits_instruction(line_) && !Self::emits_data(line_) {
+                reserved_word_count = reserved_word_count.wrapping_sub(1);
+            }
+
             let line = match line_.strip_prefix(&[Token::Label(format!(""))]) {
                 Some(without_label) => {
                     if self.verbose_log {
@@ -733,6 +1744,21 @@ impl Assembler {
                 }
                 None => &line_,
             };
+            if let [Token::Directive(directive), ..] = line {
+                if directive == "EXPORT" || directive == "WEAK" {
+                    for sym in &mut self.symbol_table {
+                        if sym.rel_addr == line_offset {
+                            sym.status = match directive.as_str() {
+                                "WEAK" => SymbolStatus::Weak,
+                                _ => SymbolStatus::Export,
+                            };
+                            break;
+                        }
+                    }
+                }
+            }
+            let line = Self::strip_export(line_);
+
             //println!("{line_offset} \t {line:?}");
             let mut line = line.iter().take(2);
             match match line.next() {
@@ -753,7 +1779,7 @@ impl Assembler {
                     // }
 
                     let unadjusted_offset = line_offset;
-                    let line_offset = line_offset + reserved_word_count;
+                    let line_offset = line_offset.wrapping_add(reserved_word_count);
 
                     if directive == "FILL" {
                         match line.next() {
@@ -764,11 +1790,19 @@ impl Assembler {
                                 {
                                     memory_writes
                                         .push((self.orig + line_offset, token.as_u16(None)));
+                                } else if let Token::Label(label) = token {
+                                    // Filled in once the symbols are placed, see fill_addresses()
+                                    self.address_fills.push((
+                                        self.orig + line_offset,
+                                        label.clone(),
+                                        tk_ln.src_ln_number,
+                                    ));
+                                    memory_writes.push((self.orig + line_offset, 0));
                                 } else {
                                     errors.push(AsmblrErr::new(
                                         Some(tk_ln.src_ln_number),
                                         format!(
-                                            "expected a number after .FILL directive, found {:?}",
+                                            "expected a number or label after .FILL directive, found {:?}",
                                             token
                                         ),
                                     ));
@@ -815,7 +1849,8 @@ impl Assembler {
                                             self.orig + line_offset + (text.bytes().len() as u16)
                                         );
                                     }
-                                    reserved_word_count += text.bytes().len() as u16;
+                                    reserved_word_count =
+                                        reserved_word_count.wrapping_add(text.bytes().len() as u16);
                                     for sym in &mut self.symbol_table {
                                         if sym.rel_addr == unadjusted_offset {
                                             sym.size_in_words = 1 + text.bytes().len() as u16;
@@ -841,8 +1876,10 @@ impl Assembler {
                                     || token.is(&Token::DecimalLiteral(NumberLiteral::new()))
                                 {
                                     let size_of_block = token.as_u16(None);
+                                    reserved_word_count = reserved_word_count
+                                        .wrapping_add(size_of_block)
+                                        .wrapping_sub(1);
                                     if size_of_block > 1 {
-                                        reserved_word_count += size_of_block - 1;
                                         for sym in &mut self.symbol_table {
                                             if sym.rel_addr == unadjusted_offset {
                                                 sym.size_in_words = size_of_block;
@@ -850,16 +1887,18 @@ impl Assembler {
                                         }
                                     }
 
-                                    for _ in 0..size_of_block {
-                                        memory_writes.push((self.orig + line_offset, 0));
+                                    for i in 0..size_of_block {
+                                        memory_writes.push((self.orig + line_offset + i, 0));
                                     }
 
                                     //if self.println!("Reserving {} words", token.as_u16(None));
                                 } else if let Token::Label(text) = token {
                                     match text.trim().parse::<u16>(){
                                         Ok(size_of_block) => {
+                                            reserved_word_count = reserved_word_count
+                                                .wrapping_add(size_of_block)
+                                                .wrapping_sub(1);
                                             if size_of_block > 1 {
-                                                reserved_word_count += size_of_block - 1;
                                                 for sym in &mut self.symbol_table {
                                                     if sym.rel_addr == unadjusted_offset {
                                                         sym.size_in_words = size_of_block;
@@ -867,6 +1906,9 @@ impl Assembler {
                                                 }
                                                 //println!("Reserving {} words", text);
                                             }
+                                            for i in 0..size_of_block {
+                                                memory_writes.push((self.orig + line_offset + i, 0));
+                                            }
                                         },
                                         Err(_) => {
                                             errors.push(AsmblrErr::new( Some(tk_ln.src_ln_number), format!("Expected a valid number decimal number after directive .BLKW, found '{text}'")))
@@ -878,17 +1920,19 @@ impl Assembler {
                         }
                     } else if directive == "IMPORT" {
                         //skip_count += 1;
-                        for sym in &mut self.symbol_table {
-                            if sym.rel_addr == unadjusted_offset {
-                                sym.status = SymbolStatus::Import;
-                                break;
+                        // What the importer expects, if declared, else of unknown kind and size
+                        let (kind, size) = match Self::import_annotation(&Self::strip_label(line_)[1..]) {
+                            Ok(annotation) => annotation.unwrap_or((SymbolKind::Code, 0)),
+                            Err(msg) => {
+                                errors.push(AsmblrErr::new(Some(tk_ln.src_ln_number), msg));
+                                (SymbolKind::Code, 0)
                             }
-                        }
-                    } else if directive == "EXPORT" {
-                        //skip_count += 1;
+                        };
                         for sym in &mut self.symbol_table {
                             if sym.rel_addr == unadjusted_offset {
-                                sym.status = SymbolStatus::Export;
+                                sym.status = SymbolStatus::Import;
+                                sym.kind = kind;
+                                sym.size_in_words = size;
                                 break;
                             }
                         }
@@ -900,6 +1944,27 @@ impl Assembler {
                     /*skip_count += 1*/
                     {}
             };
+
+            if let Some(&(addr, _)) = memory_writes.get(writes_before_line) {
+                listing.push(ListingLine {
+                    rel_addr: addr - self.orig,
+                    words: memory_writes[writes_before_line..].iter().map(|w| w.1).collect(),
+                    src_ln_number: tk_ln.src_ln_number,
+                    text: self.listing_text(tk_ln),
+                    generated: tk_ln.generated,
+                });
+            }
+        }
+        self.listing.append(&mut listing);
+
+        // Lines (and the labels on them) now move to the address of the words they emit
+        for sym in &mut self.symbol_table {
+            if let Some((_, addr)) = line_addresses.iter().find(|(line, _)| *line == sym.rel_addr) {
+                sym.rel_addr = *addr;
+            }
+        }
+        for (tk_ln, (_, addr)) in self.tokenized_lines.iter_mut().zip(line_addresses) {
+            tk_ln.rel_addr = addr;
         }
         //memory_writes =  memory_writes.into_iter().map(|w|(w.0-1,w.1)).collect();
         if !errors.is_empty() {
@@ -931,7 +1996,7 @@ impl Assembler {
         println!("\n\t\t\t\tRUNTIME LINKER");
         if link_object_files.is_some() {
             for path in link_object_files.unwrap() {
-                match read_binary_from_file(path, Endian::Little) {
+                match read_binary_from_file(path, Endian::Big) {
                     Ok(img) => {
                         let mut origin: u16 = 0;
                         for (index, val) in img.iter().enumerate() {
@@ -981,7 +2046,9 @@ impl Assembler {
                 for (addr, val) in trap.memory_writes {
                     vm.write_memory(addr, val);
                 }
-                vm.load_binary_into_memory(trap.instructions, trap.origin);
+                for (addr, val) in trap.instructions {
+                    vm.write_memory(addr, val);
+                }
             }
         });
 
@@ -1013,45 +2080,18 @@ impl Assembler {
             );
         }
 
-        vm.run_io_thread();
-        thread::sleep(time::Duration::from_millis(50));
-        loop {
-            vm.fetch();
-            vm.decode();
-            vm.execute(Some(&img.symbol_table));
-
-            if !flag_is_set(vm.read_memory(vm.mcr_address), 15) {
-                thread::sleep(time::Duration::from_millis(10));
-                print!("Ending VM instance... ");
-                vm.write_memory(vm.kbsr_address, set_flag_true(0, 14));
-                vm.write_memory(vm.dsr_address, set_flag_true(0, 14));
-                print!("Press any key to exit...");
-                while flag_is_set(vm.read_memory(vm.kbsr_address), 14)
-                    || flag_is_set(vm.read_memory(vm.dsr_address), 14)
-                {
-                    //wait for Input server to terminate.
-                    
-                    thread::sleep(time::Duration::from_millis(10));
-                }
-
-                print!("Done.\n");
-
-                break;
-            }
-            //Term::stdout().read_char();
-        }
+        vm.attach_console(default_console());
+        vm.run(Some(&img.symbol_table));
     }
 
-    pub fn parse_instructions(&mut self) -> Result<Vec<u16>, Vec<AsmblrErr>> {
-        let mut instructions: Vec<u16> = Vec::new();
+    pub fn parse_instructions(&mut self) -> Result<Vec<(u16, u16)>, Vec<AsmblrErr>> {
+        let mut instructions: Vec<(u16, u16)> = Vec::new();
+        let mut relocations = Vec::new();
         let mut errors = Vec::new();
 
         //println!("\n Removing leading labels.");
         for tk_ln in &self.tokenized_lines {
-            let mut line = match tk_ln.tokens.strip_prefix(&[Token::Label(format!(""))]) {
-                Some(without_label) => without_label,
-                None => &tk_ln.tokens,
-            };
+            let mut line = Self::strip_export(&tk_ln.tokens);
 
             if line.starts_with(&[Token::Directive(format!(""))]) {
                 //println!("Ignoring directive.");
@@ -1068,7 +2108,15 @@ impl Assembler {
                 Ok(instr) => match instr {
                     None => {}
                     Some(word) => {
-                        instructions.push(word);
+                        self.listing.push(ListingLine {
+                            rel_addr: tk_ln.rel_addr,
+                            words: vec![word],
+                            src_ln_number: tk_ln.src_ln_number,
+                            text: self.listing_text(tk_ln),
+                            generated: tk_ln.generated,
+                        });
+                        relocations.extend(self.relocation_for(line, tk_ln.rel_addr));
+                        instructions.push((self.orig.wrapping_add(tk_ln.rel_addr), word));
                     }
                 },
                 Err(msg) => errors.push(AsmblrErr::new(Some(tk_ln.src_ln_number), msg)),
@@ -1079,6 +2127,7 @@ impl Assembler {
             //println!("{errors:?}");
             return Err(errors);
         }
+        self.relocations.append(&mut relocations);
 
         // let filtered_lines = (&self.tokenized_lines)
         //     .into_iter()
@@ -1086,6 +2135,33 @@ impl Assembler {
         Ok(instructions)
     }
 
+    fn listing_text(&self, tk_ln: &TokenizedLine) -> String {
+        if tk_ln.generated {
+            let text = Token::line_to_string(&tk_ln.tokens);
+            // An instruction loading through a slot such as `__FAR0_PRINT` starts a veneer
+            let import = tk_ln.tokens.iter().skip(1).find_map(|token| match token {
+                Token::Label(slot) => slot.strip_prefix(VENEER_LABEL_PREFIX)?.split_once('_'),
+                _ => None,
+            });
+            return match import {
+                Some((_, import)) => format!("{text}\t; veneer for {import}"),
+                None => text,
+            };
+        }
+        let Some(text) = self.raw_lines.get((tk_ln.src_ln_number as usize).wrapping_sub(1)) else {
+            return Token::line_to_string(&tk_ln.tokens);
+        };
+        let text = text.trim();
+        // A `.PROC`'s labelled `RET` gave its label to the first generated `POP!`, so it is left out
+        let is_label = |token: &Token| token.is(&Token::Label(String::new())) || token.is(&Token::Directive(String::new()));
+        let source = Token::tokenize_line_in(&SourceLine::new(text, 0, 0), self.dialect).unwrap_or_default();
+        if tk_ln.tokens.first().is_some_and(is_label) || !source.first().is_some_and(is_label) {
+            return text.to_string();
+        }
+        let label_words = source.iter().take_while(|token| is_label(token)).count();
+        text.split_whitespace().skip(label_words).collect::<Vec<_>>().join(" ")
+    }
+
     fn parse_single_instr(&self, tokens: Vec<Token>, rel_addr: u16) -> Result<Option<u16>, String> {
         let target_instruction: &InstrDef; // = &InstrDef::new(OP::RES, 0, vec![]);
 
@@ -1228,23 +2304,15 @@ impl Assembler {
                 Param::Label => {
                     match &args[k] {
                         Token::Label(lbl) => {
-                            let mut symbol_value: Option<u16> = None;
-
-                            for sym in &self.symbol_table {
-                                if (self.case_insensitive_labels
-                                    && sym.name.to_ascii_uppercase() == *lbl.to_ascii_uppercase())
-                                    || sym.name == *lbl
-                                {
-                                    symbol_value = Some(
-                                        sym.rel_addr, /*add_2s_complement(sym.abs_addr, invert_sign(self.orig))*/
-                                    );
-                                }
+                            let symbol = match self.find_symbol(lbl) {
+                                Some(symbol) => symbol,
+                                None => return Err(format!("Undefined label '{lbl}'")),
+                            };
+                            if self.relocatable && matches!(symbol.status, SymbolStatus::Import) {
+                                // The offset is left as zero for the linker to patch
+                                continue;
                             }
-
-                            if symbol_value == None {
-                                return Err(format!("Undefined label '{lbl}'"));
-                            }
-                            let symbol_value = symbol_value.unwrap();
+                            let symbol_value = symbol.rel_addr; /*add_2s_complement(sym.abs_addr, invert_sign(self.orig))*/
 
                             //PC-Offset-9
                             // println!(
@@ -1255,6 +2323,24 @@ impl Assembler {
                             //     ))
                             // );
 
+                            let offset_bits =
+                                if target_instruction.opcode == (OP::JSR as u16) << 12 {
+                                    11
+                                } else {
+                                    9
+                                };
+                            let offset = symbol_value.wrapping_sub(rel_addr.wrapping_add(1)) as i16;
+                            if offset < -(1 << (offset_bits - 1)) || offset >= 1 << (offset_bits - 1) {
+                                let hint = if lbl.starts_with(LITERAL_LABEL_PREFIX) || lbl.starts_with(VENEER_LABEL_PREFIX) {
+                                    ", add a .POOL directive closer to this line"
+                                } else {
+                                    ""
+                                };
+                                return Err(format!(
+                                    "Label '{lbl}' is {offset} words away, out of range of a {offset_bits}-bit PC offset{hint}."
+                                ));
+                            }
+
                             if target_instruction.opcode == (OP::JSR as u16) << 12 {
                                 //println!("JSR!");
                                 let pc_offset_11 = truncate_to_n_bit(
@@ -1296,16 +2382,33 @@ impl Assembler {
 
 pub type SymbolTable = Vec<Symbol>;
 
+/// Labels of literal pool entries are generated with this prefix, e.g. `__LIT0_04D2`.
+pub const LITERAL_LABEL_PREFIX: &str = "__LIT";
+
+/// Labels of the pool entries holding the address of an out of range import, e.g. `__FAR0_PRINT`.
+pub const VENEER_LABEL_PREFIX: &str = "__FAR";
+
 pub fn is_instruction(s: &str) -> bool {
-    vec![
+    is_standard_instruction(s)
+        || [
+            "LDIMM", "PUSH!", "POP!", "COPY!", "ZERO!", "SP++", "SP--", "SET_COND!",
+        ]
+        .contains(&s.to_ascii_uppercase().as_str())
+}
+
+/// Mnemonics defined by the LC-3 specification (case-insensitive).
+pub fn is_standard_instruction(s: &str) -> bool {
+    [
         "AND", "ADD", "NOT", "BR", "BRZ", "BRP", "BRN", "BRNZ", "BRNZP", "BRNP", "BRZP", "LD",
-        "LDI", "LDR", "ST", "STR", "STI", "TRAP", "JMP", "RET", "JSR", "JSRR", "LEA", "HALT", "IN",
-        "OUT", "PUTS", "PUSH!", "POP!", "COPY!", "ZERO!", "SP++", "SP--", "SET_COND",
+        "LDI", "LDR", "ST", "STR", "STI", "TRAP", "JMP", "RET", "JSR", "JSRR", "LEA", "RTI",
+        "HALT", "GETC", "IN", "OUT", "PUTS", "PUTSP",
     ]
     .contains(&s.to_ascii_uppercase().as_str())
 }
 
-use std::collections::HashMap;
+pub const STANDARD_DIRECTIVES: [&str; 5] = ["ORIG", "END", "FILL", "BLKW", "STRINGZ"];
+
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Debug)]
 pub struct InstrDef {
@@ -1513,6 +2616,10 @@ impl InstructionSet {
             ),
         );
 
+        instr_set.insert(
+            String::from("GETC"),
+            InstrDef::new(OP::TRAP, 0x20, 0, vec![]),
+        );
         instr_set.insert(String::from("IN"), InstrDef::new(OP::TRAP, 0x23, 0, vec![]));
         instr_set.insert(
             String::from("OUT"),
@@ -1522,6 +2629,10 @@ impl InstructionSet {
             String::from("PUTS"),
             InstrDef::new(OP::TRAP, 0x22, 0, vec![]),
         );
+        instr_set.insert(
+            String::from("PUTSP"),
+            InstrDef::new(OP::TRAP, 0x24, 0, vec![]),
+        );
         instr_set.insert(
             String::from("HALT"),
             InstrDef::new(OP::TRAP, 0x25, 0, vec![]),
@@ -1616,7 +2727,7 @@ impl InstructionSet {
 
         //(1) Resolve opcode
         let opcode = instructions::get_opcode_16bit(mem);
-        let possible_instructions: Vec<(&String, &InstrDef)> = instr_set
+        let mut possible_instructions: Vec<(&String, &InstrDef)> = instr_set
             .iter()
             .filter(|(_, definition)| (**definition).opcode == opcode)
             .collect();
@@ -1624,12 +2735,23 @@ impl InstructionSet {
             println!("Instr not recognized");
             return disassem_text;
         }
+        // Several mnemonics can match one word (BRN and BRNZP, OUT and HALT, ADD and COPY!), so the
+        // order is fixed: standard mnemonics first, then those fixing the most bits, then by name.
+        possible_instructions.sort_by_key(|(name, definition)| {
+            (
+                !is_standard_instruction(name),
+                std::cmp::Reverse((definition.flags_word | definition.not_flags_word).count_ones()),
+                name.to_string(),
+            )
+        });
         //println!("Possible instrs: {:#?}", possible_instructions);
         //(2) resolve variant
         let (variant_name, variant_definition) =
             match possible_instructions.iter().find(|(_, definition)| {
                 (mem & definition.flags_word) == definition.flags_word
                     && mem & definition.not_flags_word == 0
+                    // An alias without operands, e.g. HALT or RET, is one exact word
+                    && (!definition.params.is_empty() || mem == opcode | definition.flags_word)
                 /*&& !(definition. as u16 && truncate_to_bit(mem, 6) == 0b1_00000)*/
                 /*&& !(definition.flags_word == 0 && ((mem & 0b0000_100_0000_00000) == 0))*/
             }) {
@@ -1637,12 +2759,14 @@ impl InstructionSet {
                     // match possible_instructions.iter().find(|(_, definition)| {
                     //     (mem & definition.flags_word) == definition.flags_word
                     //         && mem & definition.not_flags_word == 0
-                    possible_instructions
+                    match possible_instructions
                         .iter()
                         .find(|(_, definition)| {
                             (mem & definition.flags_word) == definition.flags_word
-                        })
-                        .unwrap()
+                        }) {
+                        Some(definition) => definition,
+                        None => return disassem_text,
+                    }
                     //return disassem_text;
                 }
                 Some(definition) => definition,
@@ -1668,8 +2792,9 @@ impl InstructionSet {
                 }
 
                 &Label => {
-                    //Pc-offset_9
-                    let pc_offset_9 = instructions::get_sign_ext_value(mem, 9);
+                    //Pc-offset_9, or PCoffset11 for JSR
+                    let offset_bits = if opcode == (OP::JSR as u16) << 12 { 11 } else { 9 };
+                    let pc_offset_9 = instructions::get_sign_ext_value(mem, offset_bits);
                     //disassem_text += &format!("0x{pc_offset_9:x}");
 
                     if incremented_program_counter.is_some() && symbol_table.is_some() {
@@ -1725,3 +2850,367 @@ pub enum Param {
     RegisterMultiMapped(u16, u16), /*Register that gets mapped to two bit ranges */
                                    //Imm5,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assemble_str(source: &str) -> Result<ExecutableImageOut, Vec<AsmblrErr>> {
+        let mut asm = Assembler::new("test.asm");
+        asm.load_str(source);
+        asm.assemble(vec![])
+    }
+
+    fn words(img: &ExecutableImageOut) -> Vec<u16> {
+        img.instructions.iter().map(|w| w.value).collect()
+    }
+
+    #[test]
+    pub fn proc_saves_and_restores_registers() {
+        let img = assemble_str(
+            ".ORIG x3000
+                .PROC F .EXPORT USES R1, R7
+                ADD R1, R1, #1
+                RET
+                .ENDP
+            .END",
+        )
+        .unwrap();
+
+        let push_r1 = (OP::RES as u16) << 12 | 1 << 6;
+        let push_r7 = (OP::RES as u16) << 12 | 7 << 6;
+        let pop = flag_set_mask(11);
+        assert_eq!(
+            words(&img),
+            vec![push_r1, push_r7, 0x1261, push_r7 | pop, push_r1 | pop, 0xC1C0]
+        );
+
+        let f = img.symbol_table.iter().find(|s| s.name == "F").unwrap();
+        assert_eq!(f.rel_addr, 0);
+        assert!(matches!(f.status, SymbolStatus::Export));
+        assert_eq!(img.listing.iter().filter(|ln| ln.generated).count(), 4);
+
+        // A labelled RET's label is only listed on the POP! which takes it
+        let img = assemble_str(".ORIG x3000\n.PROC F USES R1\nDONE RET\n.ENDP\n.END").unwrap();
+        let text: Vec<&str> = img.listing.iter().map(|ln| ln.text.as_str()).collect();
+        assert_eq!(&text[text.len() - 2..], &["DONE POP! R1", "RET"]);
+    }
+
+    #[test]
+    pub fn proc_without_uses_keeps_entry_label() {
+        let img = assemble_str(
+            ".ORIG x3000
+                JSR F
+                HALT
+                .PROC F
+                RET
+                .ENDP
+            .END",
+        )
+        .unwrap();
+        assert_eq!(words(&img), vec![0x4801, 0xF025, 0xC1C0]);
+    }
+
+    #[test]
+    pub fn literal_pool_after_unconditional_transfer() {
+        let img = assemble_str(
+            ".ORIG x3000
+                LDIMM R0, #1234
+                LDIMM R1, x4000
+                LDIMM R2, #1234
+                HALT
+            DATA .FILL #7
+            .END",
+        )
+        .unwrap();
+
+        // LD R0 +3, LD R1 +3, LD R2 +1, HALT, x04D2, x4000, #7
+        let mut writes: Vec<(u16, u16)> = img
+            .instructions
+            .iter()
+            .chain(img.data.iter())
+            .map(|w| (w.rel_addr, w.value))
+            .collect();
+        writes.sort();
+        assert_eq!(
+            writes,
+            vec![(0, 0x2003), (1, 0x2203), (2, 0x2401), (3, 0xF025), (4, 1234), (5, 0x4000), (6, 7)]
+        );
+        let data = img.symbol_table.iter().find(|s| s.name == "DATA").unwrap();
+        assert_eq!(data.rel_addr, 6);
+    }
+
+    #[test]
+    pub fn literal_pool_placed_within_range() {
+        let writes = |source: &str| -> Vec<(u16, u16)> {
+            let img = assemble_str(source).unwrap();
+            let mut writes: Vec<(u16, u16)> = img
+                .instructions
+                .iter()
+                .chain(img.data.iter())
+                .map(|w| (w.rel_addr, w.value))
+                .collect();
+            writes.sort();
+            writes
+        };
+
+        // Both loads share one pool, after the last RET
+        let source = ".ORIG x3000\nLDIMM R0, #5\nRET\nF LDIMM R1, #5\nRET\n.END";
+        assert_eq!(writes(source), vec![(0, 0x2003), (1, 0xC1C0), (2, 0x2201), (3, 0xC1C0), (4, 5)]);
+
+        // The last transfer before the load goes out of range
+        let mut source = String::from(".ORIG x3000\nLDIMM R0, #1\nJMP R1\n");
+        for _ in 0..300 {
+            source += "ADD R0, R0, #1\n";
+        }
+        source += "HALT\n.END";
+        let words = writes(&source);
+        assert_eq!(&words[..3], &[(0, 0x2001), (1, 0xC040), (2, 1)]);
+        assert_eq!(words.len(), 304);
+
+        // Without one, the pool is branched around
+        let source = source.replace("JMP R1\n", "");
+        let words = writes(&source);
+        assert_eq!(words[0], (0, 0x20FF));
+        assert_eq!(&words[255..258], &[(255, 0x0E01), (256, 1), (257, 0x1021)]);
+
+        // Or placed by hand
+        let source = source.replace("LDIMM R0, #1\n", "LDIMM R0, #1\nBRnzp NEXT\n.POOL\nNEXT ");
+        assert_eq!(&writes(&source)[..4], &[(0, 0x2001), (1, 0x0E01), (2, 1), (3, 0x1021)]);
+    }
+
+    #[test]
+    pub fn interleaved_code_and_data() {
+        let img = assemble_str(
+            ".ORIG x3000
+                    LEA R0, MSG
+                    BRnzp SKIP
+            MSG     .STRINGZ \"ab\"
+            SKIP    ADD R1, R1, #1
+            BUF     .BLKW 3
+            AFTER   ADD R2, R2, #2
+            TAB     .FILL AFTER
+            END     HALT
+            .END",
+        )
+        .unwrap();
+
+        // Each symbol is where its line's words are
+        let image = img.sparse();
+        let word_at = |name: &str| {
+            let symbol = img.symbol_table.iter().find(|s| s.name == name).unwrap();
+            image.get(symbol.abs_addr)
+        };
+        assert_eq!(word_at("MSG"), Some(0x0061));
+        assert_eq!(word_at("SKIP"), Some(0x1261));
+        assert_eq!(word_at("BUF"), Some(0));
+        assert_eq!(word_at("AFTER"), Some(0x14A2));
+        assert_eq!(word_at("TAB"), Some(0x3009));
+        assert_eq!(word_at("END"), Some(0xF025));
+        for ln in &img.listing {
+            for (i, word) in ln.words.iter().enumerate() {
+                assert_eq!(image.get(0x3000 + ln.rel_addr + i as u16), Some(*word));
+            }
+        }
+        assert_eq!(image.len(), 12);
+        assert_eq!(image.segments().len(), 1);
+    }
+
+    #[test]
+    pub fn sparse_image_segments() {
+        let mut image = SparseImage::default();
+        for (addr, value) in [(0x3001, 2), (0x3000, 1), (0x3100, 3), (0x3002, 4)] {
+            image.insert(addr, value);
+        }
+        assert_eq!(image.insert(0x3002, 5), Some(4));
+
+        let segments: Vec<(u16, Vec<u16>)> = image.segments().into_iter().map(|s| (s.origin, s.data)).collect();
+        assert_eq!(segments, vec![(0x3000, vec![1, 2, 5]), (0x3100, vec![3])]);
+
+        let contiguous = image.contiguous(0x3000).unwrap();
+        assert_eq!(contiguous.data.len(), 0x101);
+        assert_eq!((contiguous.data[2], contiguous.data[3], contiguous.data[0x100]), (5, 0, 3));
+        assert!(matches!(image.contiguous(0x3001), Err(0x3000)));
+    }
+
+    #[test]
+    pub fn veneers_for_far_imports() {
+        let mut lib = Assembler::new("lib.asm");
+        lib.load_str(".ORIG x4000\nPRINT .EXPORT RET\nVALUE .EXPORT .FILL x1234\n.END");
+        let lib = lib.assemble(vec![]).unwrap();
+        let sym_path = std::env::temp_dir().join(format!("lc3-veneer-test-{}.sym", std::process::id()));
+        let sym_path = sym_path.to_str().unwrap();
+        write_symbols_to_file(sym_path, &lib).unwrap();
+
+        let mut asm = Assembler::new("prog.asm");
+        asm.load_str(
+            ".ORIG x3000
+            JSR PRINT
+            LD R1, VALUE
+            LDI R3, VALUE
+            HALT
+            PRINT .IMPORT
+            VALUE .IMPORT
+            .END",
+        );
+        let img = asm.assemble(vec![sym_path]);
+
+        // The pool is placed within range of each veneer, however far apart they are
+        let mut far = Assembler::new("far.asm");
+        far.load_str(".ORIG x3000\nJSR PRINT\n.BLKW 300\nJSR PRINT\nHALT\nPRINT .IMPORT\n.END");
+        let far = far.assemble(vec![sym_path]);
+        std::fs::remove_file(sym_path).unwrap();
+        let img = img.unwrap();
+        let far = far.unwrap();
+        let slots: Vec<&str> = far
+            .listing
+            .iter()
+            .filter(|ln| ln.text.contains(".FILL PRINT"))
+            .map(|ln| ln.text.as_str())
+            .collect();
+        assert_eq!(slots, vec!["__FAR0_PRINT .FILL PRINT", "__FAR1_PRINT .FILL PRINT"]);
+
+        let mut writes: Vec<(u16, u16)> = img
+            .instructions
+            .iter()
+            .chain(img.data.iter())
+            .map(|w| (w.rel_addr, w.value))
+            .collect();
+        writes.sort();
+        // LD R7, LDI R1 and LDI R3 from the pool after HALT, which holds x4000 and x4001
+        assert_eq!(
+            writes,
+            vec![(0, 0x2E05), (1, 0x41C0), (2, 0xA204), (3, 0xA603), (4, 0x66C0), (5, 0xF025), (6, 0x4000), (7, 0x4001)]
+        );
+        let veneers: Vec<&str> = img.listing.iter().filter(|ln| ln.generated).map(|ln| ln.text.as_str()).collect();
+        assert_eq!(veneers[0], "LD R7, __FAR0_PRINT\t; veneer for PRINT");
+        assert_eq!(veneers[2], "LDI R1, __FAR0_VALUE\t; veneer for VALUE");
+        assert_eq!(veneers[5], "__FAR0_PRINT .FILL PRINT");
+    }
+
+    #[test]
+    pub fn strict_dialect_rejects_extensions() {
+        let strict = |source: &str| {
+            let mut asm = Assembler::new("test.asm");
+            asm.dialect = Dialect::Strict;
+            asm.load_str(source);
+            asm.assemble(vec![])
+        };
+
+        assert!(strict(".ORIG x3000\nadd R0, R0, #1\nhalt\n.END").is_ok());
+        let traps = strict(".ORIG x3000\nGETC\nIN\nOUT\nPUTS\nputsp\nHALT\n.END").unwrap();
+        assert_eq!(words(&traps), vec![0xF020, 0xF023, 0xF021, 0xF022, 0xF024, 0xF025]);
+        for extension in ["PUSH! R1", "pop! R1", "SP++", "ZERO! R0", "LDIMM R0, #300"] {
+            let result = strict(&format!(".ORIG x3000\n{extension}\nHALT\n.END"));
+            assert!(matches!(
+                result.err().as_deref(),
+                Some([AsmblrErr { error_type: AsmErrorType::CompatibilityError, .. }, ..])
+            ));
+        }
+        assert!(strict(".ORIG x3000\nF .EXPORT HALT\n.END").is_err());
+
+        // Directives are case-insensitive, and syntax errors are still reported
+        assert!(strict(".orig x3000\nN .fill #5\nhalt\n.end").is_ok());
+        assert!(matches!(
+            strict(".ORIG x3000\n.BOGUS x1\nHALT\n.END").err().as_deref(),
+            Some([AsmblrErr { error_type: AsmErrorType::SyntaxError, .. }, ..])
+        ));
+    }
+
+    #[test]
+    pub fn compat_dialect_matches_extended() {
+        let compat = |source: &str| {
+            let mut asm = Assembler::new("test.asm");
+            asm.dialect = Dialect::Compat;
+            asm.load_str(source);
+            asm.assemble(vec![])
+        };
+
+        let img = compat(
+            ".orig x3000
+            loop:   ADD R0, R0, #-1;count down
+                    BRp loop
+            done:
+                    HALT
+            n:      .fill #5
+                    .end",
+        )
+        .unwrap();
+        let expected = assemble_str(
+            ".ORIG x3000
+            loop ADD R0, R0, #-1
+                 BRp loop
+            done HALT
+            n    .FILL #5
+            .END",
+        )
+        .unwrap();
+        assert_eq!(words(&img), words(&expected));
+        assert_eq!(img.data[0].value, 5);
+        assert!(img.symbol_table.iter().any(|s| s.name == "done"));
+
+        // .EXTERNAL declares an import, which then has to be resolved
+        let result = compat(".orig x3000\n.external PRINT\nJSR PRINT\nHALT\n.end");
+        assert!(matches!(
+            result.err().as_deref(),
+            Some([AsmblrErr { error_type: AsmErrorType::LinkError, .. }])
+        ));
+        assert!(assemble_str(".orig x3000\nHALT\n.end").is_err());
+    }
+
+    #[test]
+    pub fn relocations_record_label_references() {
+        let mut asm = Assembler::new("test.asm");
+        asm.relocatable = true;
+        asm.load_str(
+            ".ORIG x3000
+            PRINT .IMPORT
+                  JSR PRINT
+                  LEA R0, MSG
+                  HALT
+            MSG   .STRINGZ \"hi\"
+            PTR   .FILL PRINT
+            SELF  .FILL MSG
+            .END",
+        );
+        let img = asm.assemble(vec![]).unwrap();
+
+        // The import is left for the linker, the local reference is already encoded
+        assert_eq!(words(&img), vec![0x4800, 0xE001, 0xF025]);
+        let relocations: Vec<(u16, RelocationKind, &str)> = img
+            .relocations
+            .iter()
+            .map(|r| (r.rel_addr, r.kind, r.symbol.as_str()))
+            .collect();
+        assert_eq!(
+            relocations,
+            vec![
+                (0, RelocationKind::PcOffset11, "PRINT"),
+                (1, RelocationKind::PcOffset9, "MSG"),
+                (6, RelocationKind::Absolute, "PRINT"),
+                (7, RelocationKind::Absolute, "MSG"),
+            ]
+        );
+        let fills: Vec<u16> = img.data.iter().filter(|w| w.rel_addr >= 6).map(|w| w.value).collect();
+        assert_eq!(fills, vec![0, 0x3003]);
+
+        // Without --relocatable, imports must be resolved
+        assert!(assemble_str(".ORIG x3000\nF .IMPORT\nJSR F\n.END").is_err());
+    }
+
+    #[test]
+    pub fn relocation_patch() {
+        assert_eq!(RelocationKind::PcOffset11.patch(0x4800, 0x3000, 0x3100), Ok(0x48FF));
+        assert_eq!(RelocationKind::PcOffset9.patch(0x2000, 0x3010, 0x3000), Ok(0x2000 | 0x1EF));
+        assert_eq!(RelocationKind::Absolute.patch(0, 0x3000, 0x4567), Ok(0x4567));
+        assert!(RelocationKind::PcOffset9.patch(0x2000, 0x3000, 0x3200).is_err());
+    }
+
+    #[test]
+    pub fn proc_errors() {
+        assert!(assemble_str(".ORIG x3000\n.PROC F USES R1\nRET\n.END").is_err());
+        assert!(assemble_str(".ORIG x3000\nRET\n.ENDP\n.END").is_err());
+        assert!(assemble_str(".ORIG x3000\n.PROC F USES R6\nRET\n.ENDP\n.END").is_err());
+        assert!(assemble_str(".ORIG x3000\n.PROC F USES R1,\nRET\n.ENDP\n.END").is_err());
+    }
+}
@@ -0,0 +1,429 @@
+use crate::assemble::*;
+use crate::error::AsmblrErr;
+use crate::linker::{link_name, resolve_exports, ObjectModule};
+use crate::virtual_machine::OP;
+use std::collections::HashMap;
+
+/// The words of a module from a label up to the next label: a routine, part of one (e.g. a
+/// loop), or a data item.
+struct Section {
+    start: u16,
+    end: u16,
+}
+
+/// Drops the routines and data of `modules` which cannot be reached from the entry point (the
+/// start of the first module), the exports of the first module, or the symbols named in `keep`.
+///
+/// References are followed through the relocations (`JSR`, `LEA`, `BR`, `.FILL LABEL`, ...), PC
+/// offsets written as numbers, and code which falls through into the next label. Words covered
+/// by a data or constant symbol (its size in words from its label) are never read as
+/// instructions. Each module keeps its origin and closes up around what was removed; modules
+/// with nothing left are removed altogether. Returns a report of what was removed.
+pub fn eliminate_dead_code(modules: &mut Vec<ObjectModule>, keep: &[String]) -> Result<Vec<String>, Vec<AsmblrErr>> {
+    let link_error = |msg: String| AsmblrErr::new(None, msg).link_error().clone();
+    if modules.is_empty() {
+        return Ok(vec![]);
+    }
+
+    // Module index and relative address of each exported name
+    let exports: HashMap<String, (usize, u16)> = resolve_exports(modules)?
+        .into_iter()
+        .filter_map(|(name, (addr, module))| {
+            let i = modules.iter().position(|m| m.name == module)?;
+            Some((name, (i, addr.wrapping_sub(modules[i].image.origin))))
+        })
+        .collect();
+    let sections: Vec<Vec<Section>> = modules.iter().map(sections_of).collect();
+
+    let mut live: Vec<Vec<bool>> = sections.iter().map(|s| vec![false; s.len()]).collect();
+    let mut pending: Vec<(usize, u16)> = vec![(0, 0)];
+    for symbol in modules[0].symbols.iter().filter(|s| s.status.is_export()) {
+        pending.push((0, symbol.rel_addr));
+    }
+    let mut errors = Vec::new();
+    for name in keep {
+        let defined = exports.get(&link_name(name)).copied().or_else(|| {
+            modules.iter().enumerate().find_map(|(i, module)| {
+                module
+                    .symbols
+                    .iter()
+                    .find(|s| s.name.eq_ignore_ascii_case(name) && !matches!(s.status, SymbolStatus::Import))
+                    .map(|s| (i, s.rel_addr))
+            })
+        });
+        match defined {
+            Some(root) => pending.push(root),
+            None => errors.push(link_error(format!("Unable to keep '{name}', no object file defines it."))),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    while let Some((m, rel_addr)) = pending.pop() {
+        let module = &modules[m];
+        let Some(s) = section_at(&sections[m], rel_addr) else {
+            continue;
+        };
+        if live[m][s] {
+            continue;
+        }
+        live[m][s] = true;
+        let section = &sections[m][s];
+
+        for relocation in relocations_in(module, section) {
+            match module.symbols.iter().find(|s| s.name == relocation.symbol) {
+                Some(symbol) if matches!(symbol.status, SymbolStatus::Import) => {
+                    // Left to the link step to report if nothing exports it
+                    if let Some(target) = exports.get(&link_name(&symbol.name)) {
+                        pending.push(*target);
+                    }
+                }
+                Some(symbol) => pending.push((m, symbol.rel_addr)),
+                None => {}
+            }
+        }
+        for rel_addr in section.start..section.end {
+            if let Some(target) = numeric_target(module, rel_addr) {
+                if target >= 0 && (target as usize) < module.image.data.len() {
+                    pending.push((m, target as u16));
+                }
+            }
+        }
+        let last = section.end - 1;
+        if !is_data(module, last) && !ends_flow(module.image.data[last as usize]) {
+            pending.push((m, section.end));
+        }
+    }
+
+    let mut report = Vec::new();
+    let total: usize = modules.iter().map(|module| module.image.data.len()).sum();
+    let mut removed_words = 0;
+    let mut removed_modules = Vec::new();
+    for (m, module) in modules.iter_mut().enumerate() {
+        let dead: Vec<&Section> = sections[m].iter().zip(&live[m]).filter(|(_, live)| !**live).map(|(s, _)| s).collect();
+        if dead.is_empty() {
+            continue;
+        }
+        let names = |section: &Section| -> Vec<String> {
+            let names: Vec<String> = module
+                .symbols
+                .iter()
+                .filter(|s| !matches!(s.status, SymbolStatus::Import))
+                .filter(|s| s.rel_addr >= section.start && s.rel_addr < section.end)
+                .map(|s| s.name.clone())
+                .collect();
+            if names.is_empty() {
+                vec![String::from("(unlabelled)")]
+            } else {
+                names
+            }
+        };
+
+        if dead.len() == sections[m].len() {
+            let names: Vec<String> = dead.iter().flat_map(|section| names(section)).collect();
+            report.push(format!(
+                "Removed '{}' ({} words): {}",
+                module.name,
+                module.image.data.len(),
+                names.join(", ")
+            ));
+            removed_words += module.image.data.len();
+            removed_modules.push(m);
+            continue;
+        }
+        for section in &dead {
+            report.push(format!(
+                "Removed {} from '{}' (x{:04x}, {} words)",
+                names(section).join(", "),
+                module.name,
+                module.image.origin.wrapping_add(section.start),
+                section.end - section.start
+            ));
+            removed_words += (section.end - section.start) as usize;
+        }
+        if let Err(mut e) = compact(module, &sections[m], &live[m]) {
+            errors.append(&mut e);
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    for m in removed_modules.into_iter().rev() {
+        modules.remove(m);
+    }
+    report.push(format!("Removed {removed_words} of {total} words."));
+    Ok(report)
+}
+
+/// The sections of `module`, split at its start and at each of its labels.
+fn sections_of(module: &ObjectModule) -> Vec<Section> {
+    let len = module.image.data.len() as u16;
+    let mut starts: Vec<u16> = module
+        .symbols
+        .iter()
+        .filter(|s| !matches!(s.status, SymbolStatus::Import) && s.rel_addr < len)
+        .map(|s| s.rel_addr)
+        .collect();
+    starts.push(0);
+    starts.sort();
+    starts.dedup();
+
+    let ends = starts.iter().skip(1).copied().chain([len]);
+    starts
+        .iter()
+        .zip(ends)
+        .filter(|(start, end)| **start < *end)
+        .map(|(start, end)| Section { start: *start, end })
+        .collect()
+}
+
+fn section_at(sections: &[Section], rel_addr: u16) -> Option<usize> {
+    sections
+        .iter()
+        .position(|section| rel_addr >= section.start && rel_addr < section.end)
+}
+
+fn relocations_in<'a>(module: &'a ObjectModule, section: &'a Section) -> impl Iterator<Item = &'a Relocation> {
+    module
+        .relocations
+        .iter()
+        .filter(|r| r.rel_addr >= section.start && r.rel_addr < section.end)
+}
+
+/// Whether the word at `rel_addr` is covered by a data or constant symbol.
+fn is_data(module: &ObjectModule, rel_addr: u16) -> bool {
+    module.symbols.iter().any(|symbol| {
+        !matches!(symbol.status, SymbolStatus::Import)
+            && !matches!(symbol.kind, SymbolKind::Code)
+            && rel_addr >= symbol.rel_addr
+            && (rel_addr as u32) < symbol.rel_addr as u32 + symbol.size_in_words as u32
+    })
+}
+
+/// The relative address an instruction at `rel_addr` refers to with a PC offset written as a
+/// number, i.e. without a relocation.
+fn numeric_target(module: &ObjectModule, rel_addr: u16) -> Option<i32> {
+    if is_data(module, rel_addr) || module.relocations.iter().any(|r| r.rel_addr == rel_addr) {
+        return None;
+    }
+    let word = module.image.data[rel_addr as usize];
+    let bits = pc_offset_bits(word)?;
+    let offset = ((word << (16 - bits)) as i16) >> (16 - bits);
+    Some(rel_addr as i32 + 1 + offset as i32)
+}
+
+/// The width of the PC offset of `word`, if it is an instruction with one.
+fn pc_offset_bits(word: u16) -> Option<u16> {
+    let opcode = word >> 12;
+    if opcode == OP::BR as u16 {
+        // BR with none of n, z and p set never branches
+        (word & 0x0E00 != 0).then_some(9)
+    } else if opcode == OP::JSR as u16 {
+        (word & 0x0800 != 0).then_some(11)
+    } else if [OP::LD, OP::ST, OP::LDI, OP::STI, OP::LEA].iter().any(|op| *op as u16 == opcode) {
+        Some(9)
+    } else {
+        None
+    }
+}
+
+/// Whether execution never continues after `word`: `BRnzp`, `JMP`/`RET`, `RTI` or `HALT`.
+fn ends_flow(word: u16) -> bool {
+    let opcode = word >> 12;
+    (opcode == OP::BR as u16 && word & 0x0E00 == 0x0E00)
+        || opcode == OP::JMP as u16
+        || opcode == OP::RTI as u16
+        || word == 0xF025
+}
+
+/// Removes the sections of `module` which are not `live`, moving the rest down to close the gaps.
+/// Relocations and symbols move with their words (the link step patches the relocations), and
+/// PC offsets written as numbers are rewritten.
+fn compact(module: &mut ObjectModule, sections: &[Section], live: &[bool]) -> Result<(), Vec<AsmblrErr>> {
+    let len = module.image.data.len();
+    let mut new_addr: Vec<Option<u16>> = vec![None; len + 1];
+    let mut next = 0u16;
+    for (section, _) in sections.iter().zip(live).filter(|(_, live)| **live) {
+        for rel_addr in section.start..section.end {
+            new_addr[rel_addr as usize] = Some(next);
+            next += 1;
+        }
+    }
+    new_addr[len] = Some(next);
+
+    let origin = module.image.origin;
+    let mut errors = Vec::new();
+    let mut data = Vec::with_capacity(next as usize);
+    for rel_addr in 0..len as u16 {
+        let Some(moved_to) = new_addr[rel_addr as usize] else {
+            continue;
+        };
+        let mut word = module.image.data[rel_addr as usize];
+        if let Some(target) = numeric_target(module, rel_addr) {
+            let target = match new_addr.get(target as usize) {
+                Some(Some(target_moved_to)) if target >= 0 => origin.wrapping_add(*target_moved_to),
+                _ => origin.wrapping_add(target as u16),
+            };
+            let kind = match pc_offset_bits(word) {
+                Some(11) => RelocationKind::PcOffset11,
+                _ => RelocationKind::PcOffset9,
+            };
+            match kind.patch(word, origin.wrapping_add(moved_to), target) {
+                Ok(patched) => word = patched,
+                Err(msg) => errors.push(
+                    AsmblrErr::new(
+                        None,
+                        format!("Unable to move x{:04x} in '{}': {msg}", origin.wrapping_add(rel_addr), module.name),
+                    )
+                    .link_error()
+                    .clone(),
+                ),
+            }
+        }
+        data.push(word);
+    }
+
+    let relocations: Vec<Relocation> = module
+        .relocations
+        .iter()
+        .filter_map(|r| {
+            let rel_addr = (*new_addr.get(r.rel_addr as usize)?)?;
+            Some(Relocation { rel_addr, ..r.clone() })
+        })
+        .collect();
+    let symbols: Vec<Symbol> = module
+        .symbols
+        .iter()
+        .filter_map(|s| match s.status {
+            // Only the imports which are still referred to
+            SymbolStatus::Import => relocations.iter().any(|r| r.symbol == s.name).then(|| s.clone()),
+            _ => {
+                let rel_addr = (*new_addr.get(s.rel_addr as usize)?)?;
+                Some(Symbol {
+                    rel_addr,
+                    abs_addr: origin.wrapping_add(rel_addr),
+                    ..s.clone()
+                })
+            }
+        })
+        .collect();
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    module.image.data = data;
+    module.relocations = relocations;
+    module.symbols = symbols;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::linker::link;
+
+    fn module(name: &str, source: &str) -> ObjectModule {
+        let mut asm = Assembler::new(name);
+        asm.relocatable = true;
+        asm.load_str(source);
+        let img = match asm.assemble(vec![]) {
+            Ok(img) => img,
+            Err(errors) => panic!("{errors:?}"),
+        };
+
+        let writes: Vec<MemoryWrite> = img
+            .instructions
+            .iter()
+            .chain(img.data.iter())
+            .map(|w| MemoryWrite { rel_addr: w.rel_addr, value: w.value })
+            .collect();
+        ObjectModule {
+            name: name.to_string(),
+            image: ExecutableImageIn::from_writes(img.origin, &writes),
+            symbols: img.symbol_table,
+            relocations: img.relocations,
+        }
+    }
+
+    const MAIN: &str = ".ORIG x3000
+        JSR PRINT
+        BRnzp DONE
+        UNUSED ADD R0, R0, #1
+        RET
+        DONE HALT
+        PRINT .IMPORT
+        .END";
+
+    const LIB: &str = ".ORIG x3100
+        PRINT .EXPORT LEA R0, MSG
+        BRz LOOP
+        ADD R0, R0, #0
+        LOOP ADD R1, R1, #-1
+        BRp LOOP
+        RET
+        SQUARE .EXPORT ADD R0, R0, R0
+        RET
+        MSG .STRINGZ \"hi\"
+        TABLE .FILL SQUARE
+        .END";
+
+    const UNUSED: &str = ".ORIG x3200
+        CLEAR .EXPORT AND R0, R0, #0
+        RET
+        .END";
+
+    #[test]
+    pub fn removes_unreferenced_code() {
+        let mut modules = vec![module("main.obj", MAIN), module("lib.obj", LIB), module("unused.obj", UNUSED)];
+        let report = eliminate_dead_code(&mut modules, &[]).unwrap();
+        assert_eq!(
+            report,
+            vec![
+                "Removed UNUSED from 'main.obj' (x3002, 2 words)",
+                "Removed SQUARE from 'lib.obj' (x3106, 2 words)",
+                "Removed TABLE from 'lib.obj' (x310b, 1 words)",
+                "Removed 'unused.obj' (2 words): CLEAR",
+                "Removed 7 of 19 words."
+            ]
+        );
+        assert_eq!(modules.len(), 2);
+
+        let img = link("prog.obj", modules).unwrap();
+        let words: Vec<(u16, u16)> = img.data.iter().map(|w| (w.rel_addr, w.value)).collect();
+        assert_eq!(
+            words,
+            vec![
+                (0, 0x48FF),  // JSR PRINT
+                (1, 0x0E00),  // BRnzp DONE, rewritten now that it is the next word
+                (2, 0xF025),
+                (0x100, 0xE005), // LEA R0, MSG, which has moved down
+                (0x101, 0x0401), // BRz LOOP still skips one word
+                (0x102, 0x1020),
+                (0x103, 0x127F),
+                (0x104, 0x03FE),
+                (0x105, 0xC1C0),
+                (0x106, 0x0068),
+                (0x107, 0x0069),
+                (0x108, 0x0000),
+            ]
+        );
+        let msg = img.symbol_table.iter().find(|s| s.name == "MSG").unwrap();
+        assert_eq!(msg.abs_addr, 0x3106);
+        assert!(img.symbol_table.iter().all(|s| s.name != "SQUARE"));
+    }
+
+    #[test]
+    pub fn keeps_roots() {
+        // Exports of the first module, and symbols named to keep
+        let main = MAIN.replace("UNUSED ADD", "UNUSED .EXPORT ADD");
+        let mut modules = vec![module("main.obj", &main), module("lib.obj", LIB), module("unused.obj", UNUSED)];
+        let report = eliminate_dead_code(&mut modules, &[String::from("CLEAR"), String::from("TABLE")]).unwrap();
+        assert_eq!(report, vec!["Removed 0 of 19 words."]);
+        assert_eq!(modules.len(), 3);
+
+        let mut modules = vec![module("main.obj", MAIN)];
+        assert!(eliminate_dead_code(&mut modules, &[String::from("NOWHERE")]).is_err());
+    }
+}
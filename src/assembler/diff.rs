@@ -0,0 +1,166 @@
+use crate::assemble::*;
+
+/// How two images differ, word by word at each address and symbol by symbol.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImageDiff {
+    /// `(address, word in the first image, word in the second)` wherever they differ, `None`
+    /// where an image writes nothing.
+    pub words: Vec<(u16, Option<u16>, Option<u16>)>,
+    /// The number of words each image writes.
+    pub sizes: (usize, usize),
+    pub only_in_first: Vec<String>,
+    pub only_in_second: Vec<String>,
+    /// `(name, address in the first image, address in the second)` of each symbol which moved.
+    pub moved: Vec<(String, u16, u16)>,
+}
+
+impl ImageDiff {
+    /// Compares `first` and `second` by address, and their symbols (imports aside) by name.
+    pub fn compare(first: &SparseImage, second: &SparseImage, first_symbols: &[Symbol], second_symbols: &[Symbol]) -> Self {
+        let mut addresses: Vec<u16> = first.iter().chain(second.iter()).map(|(addr, _)| addr).collect();
+        addresses.sort();
+        addresses.dedup();
+        let words = addresses
+            .into_iter()
+            .map(|addr| (addr, first.get(addr), second.get(addr)))
+            .filter(|(_, a, b)| a != b)
+            .collect();
+
+        let first_symbols = defined(first_symbols);
+        let second_symbols = defined(second_symbols);
+        let find = |symbols: &[&Symbol], name: &str| symbols.iter().find(|s| s.name == name).map(|s| s.abs_addr);
+        let mut diff = Self {
+            words,
+            sizes: (first.len(), second.len()),
+            ..Self::default()
+        };
+        for symbol in &first_symbols {
+            match find(&second_symbols, &symbol.name) {
+                None => diff.only_in_first.push(symbol.name.clone()),
+                Some(addr) if addr != symbol.abs_addr => diff.moved.push((symbol.name.clone(), symbol.abs_addr, addr)),
+                Some(_) => {}
+            }
+        }
+        for symbol in &second_symbols {
+            if find(&first_symbols, &symbol.name).is_none() {
+                diff.only_in_second.push(symbol.name.clone());
+            }
+        }
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty() && self.only_in_first.is_empty() && self.only_in_second.is_empty() && self.moved.is_empty()
+    }
+
+    /// The differing words side by side, each disassembled using the symbols of its own image
+    /// for branch targets, then a summary.
+    pub fn lines(&self, names: (&str, &str), symbols: (&[Symbol], &[Symbol])) -> Vec<String> {
+        let first_symbols: SymbolTable = defined(symbols.0).into_iter().cloned().collect();
+        let second_symbols: SymbolTable = defined(symbols.1).into_iter().cloned().collect();
+        let column = |word: Option<u16>, addr: u16, symbols: &SymbolTable| match word {
+            Some(word) => {
+                let text = InstructionSet::dissasemble_memory(word, Some(addr.wrapping_add(1)), Some(symbols), None);
+                format!("{word:04X}  {:24}", text.replace('\t', " ").trim())
+            }
+            None => format!("{:4}  {:24}", "----", ""),
+        };
+
+        let mut lines = vec![format!(";{:5}\t{:30}\t{}", "Addr", names.0, names.1)];
+        for (addr, first, second) in &self.words {
+            let line = format!(
+                "x{addr:04X}\t{}\t{}",
+                column(*first, *addr, &first_symbols),
+                column(*second, *addr, &second_symbols)
+            );
+            lines.push(line.trim_end().to_string());
+        }
+        lines.push(String::new());
+        lines.push(format!(
+            "Words:\t{} in {}, {} in {}, {} differ",
+            self.sizes.0,
+            names.0,
+            self.sizes.1,
+            names.1,
+            self.words.len()
+        ));
+        if !self.only_in_first.is_empty() {
+            lines.push(format!("Only in {}:\t{}", names.0, self.only_in_first.join(", ")));
+        }
+        if !self.only_in_second.is_empty() {
+            lines.push(format!("Only in {}:\t{}", names.1, self.only_in_second.join(", ")));
+        }
+        for (name, first, second) in &self.moved {
+            lines.push(format!("Moved:\t{name} x{first:04X} -> x{second:04X}"));
+        }
+        if self.is_empty() {
+            lines.push(String::from("The images are identical."));
+        }
+        lines
+    }
+}
+
+fn defined(symbols: &[Symbol]) -> Vec<&Symbol> {
+    symbols
+        .iter()
+        .filter(|symbol| !matches!(symbol.status, SymbolStatus::Import))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assemble(source: &str) -> (SparseImage, SymbolTable) {
+        let mut asm = Assembler::new("prog.asm");
+        asm.load_str(source);
+        let img = asm.assemble(vec![]).unwrap();
+        (img.sparse(), img.symbol_table)
+    }
+
+    const REFERENCE: &str = ".ORIG x3000
+        AND R0, R0, #0
+LOOP    ADD R0, R0, #1
+        BRp LOOP
+        HALT
+COUNT   .FILL #5
+        .END";
+
+    #[test]
+    pub fn identical_images() {
+        let (image, symbols) = assemble(REFERENCE);
+        let diff = ImageDiff::compare(&image, &image, &symbols, &symbols);
+        assert!(diff.is_empty());
+        assert_eq!(diff.sizes, (5, 5));
+        assert_eq!(
+            diff.lines(("a.obj", "b.obj"), (&symbols, &symbols)).last().unwrap(),
+            "The images are identical."
+        );
+    }
+
+    #[test]
+    pub fn differing_words_and_symbols() {
+        let (reference, reference_symbols) = assemble(REFERENCE);
+        let submitted = REFERENCE
+            .replace("ADD R0, R0, #1", "ADD R0, R0, #2")
+            .replace("COUNT   .FILL #5", "        NOT R1, R1\nTOTAL   .FILL #5");
+        let (submitted, submitted_symbols) = assemble(&submitted);
+
+        let diff = ImageDiff::compare(&reference, &submitted, &reference_symbols, &submitted_symbols);
+        assert_eq!(
+            diff.words,
+            vec![(0x3001, Some(0x1021), Some(0x1022)), (0x3004, Some(5), Some(0x927F)), (0x3005, None, Some(5))]
+        );
+        assert_eq!(diff.sizes, (5, 6));
+        assert_eq!(diff.only_in_first, vec!["COUNT"]);
+        assert_eq!(diff.only_in_second, vec!["TOTAL"]);
+        assert!(diff.moved.is_empty());
+
+        let lines = diff.lines(("ref.obj", "student.obj"), (&reference_symbols, &submitted_symbols));
+        assert!(lines[1].starts_with("x3001\t1021  ADD"));
+        assert!(lines[1].contains("\t1022  ADD"));
+        assert!(lines[3].starts_with("x3005\t----"));
+        assert_eq!(lines[5], "Words:\t5 in ref.obj, 6 in student.obj, 3 differ");
+        assert_eq!(lines[6], "Only in ref.obj:\tCOUNT");
+    }
+}
@@ -0,0 +1,218 @@
+use crate::error::AsmblrErr;
+use crate::tokenizer::*;
+
+/// Width of the label column; the mnemonic starts here.
+pub const LABEL_WIDTH: usize = 16;
+pub const MNEMONIC_WIDTH: usize = 8;
+pub const OPERANDS_WIDTH: usize = 24;
+
+/// Re-emits source lines in canonical columns (label, mnemonic, operands, comment), with
+/// mnemonics, directives and registers in upper case. Comments and blank lines are kept.
+/// Formatting already formatted source leaves it unchanged.
+pub fn format_source(raw_lines: &[String]) -> Result<Vec<String>, Vec<AsmblrErr>> {
+    let mut formatted = Vec::new();
+    let mut errors = Vec::new();
+
+    for (i, line) in raw_lines.iter().enumerate() {
+        match format_line(line) {
+            Ok(line) => formatted.push(line),
+            Err(msg) => errors.push(AsmblrErr::new(Some(i as u16 + 1), msg)),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(formatted)
+}
+
+pub fn format_line(line: &str) -> Result<String, String> {
+    let (code, comment) = split_comment(line);
+
+    if code.trim().is_empty() {
+        return Ok(match comment {
+            None => String::new(),
+            // Comments keep to the first column if they started there, else they are indented
+            Some(comment) if line.starts_with(';') => comment.trim_end().to_string(),
+            Some(comment) => format!("{:LABEL_WIDTH$}{}", "", comment.trim_end()),
+        });
+    }
+
+    let tokens = Token::tokenize_str_w_err(code)?;
+    let words: Vec<String> = tokens
+        .iter()
+        .zip(operand_words(code, &tokens))
+        .map(|(token, word)| match token {
+            Token::Instruction(_) => normalize_mnemonic(&word),
+            Token::Directive(_) | Token::Register(_) => word.to_ascii_uppercase(),
+            _ => word,
+        })
+        .collect();
+
+    // A label, and any .EXPORT/.WEAK/.IMPORT annotating it, share the label column
+    let mut label = String::new();
+    let mut i = 0;
+    if let Some(Token::Label(_)) = tokens.first() {
+        label += &words[0];
+        i = 1;
+        while let Some(Token::Directive(dir)) = tokens.get(i) {
+            if dir != "EXPORT" && dir != "WEAK" && dir != "IMPORT" {
+                break;
+            }
+            label += " ";
+            label += &words[i];
+            i += 1;
+        }
+    }
+
+    let mnemonic = words.get(i).cloned().unwrap_or_default();
+    let mut operands = String::new();
+    for (token, word) in tokens.iter().zip(&words).skip(i + 1) {
+        if token.is(&Token::Comma) {
+            operands.push(',');
+            continue;
+        }
+        if !operands.is_empty() {
+            operands.push(' ');
+        }
+        operands += word;
+    }
+
+    let mut formatted = column(&label, LABEL_WIDTH);
+    if let Some(comment) = comment {
+        formatted += &column(&mnemonic, MNEMONIC_WIDTH);
+        formatted += &column(&operands, OPERANDS_WIDTH);
+        formatted += comment.trim_end();
+    } else {
+        formatted += &column(&mnemonic, MNEMONIC_WIDTH);
+        formatted += &operands;
+    }
+    Ok(formatted.trim_end().to_string())
+}
+
+/// Upper case, except for the condition codes of a branch, e.g. `BRnz`.
+fn normalize_mnemonic(mnemonic: &str) -> String {
+    let upper = mnemonic.to_ascii_uppercase();
+    match upper.strip_prefix("BR") {
+        Some(conditions) => format!("BR{}", conditions.to_ascii_lowercase()),
+        None => upper,
+    }
+}
+
+/// Pads `text` to `width`, always leaving at least one space after it.
+fn column(text: &str, width: usize) -> String {
+    if text.len() < width {
+        format!("{text:width$}")
+    } else {
+        format!("{text} ")
+    }
+}
+
+/// Splits a line at the first `;` which is not inside a string literal.
+fn split_comment(line: &str) -> (&str, Option<&str>) {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            ';' if !in_string => return (&line[..i], Some(&line[i..])),
+            _ => {}
+        }
+    }
+    (line, None)
+}
+
+/// The source text of each token, so literals are re-emitted as they were written. Commas are
+/// kept as `,`; if the text cannot be matched up with the tokens they are rendered instead.
+fn operand_words(code: &str, tokens: &[Token]) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut in_string = false;
+
+    for c in code.trim().chars() {
+        match c {
+            '"' => {
+                in_string = !in_string;
+                current.push(c);
+            }
+            ',' | ' ' | '\t' if !in_string => {
+                if !current.is_empty() {
+                    words.push(std::mem::take(&mut current));
+                }
+                if c == ',' {
+                    words.push(String::from(","));
+                }
+            }
+            _ => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        words.push(current);
+    }
+
+    if words.len() != tokens.len() {
+        return tokens.iter().map(|token| token.to_string()).collect();
+    }
+    words
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn columns() {
+        assert_eq!(
+            format_line("loop\tadd r1,r1,#-1 ; count down").unwrap(),
+            format!("{:16}{:8}{:24}; count down", "loop", "ADD", "R1, R1, #-1")
+        );
+        assert_eq!(
+            format_line("  .ORIG   x3000").unwrap(),
+            format!("{:16}{:8}x3000", "", ".ORIG")
+        );
+        assert_eq!(
+            format_line("f .EXPORT  ld R0, N").unwrap(),
+            format!("{:16}{:8}R0, N", "f .EXPORT", "LD")
+        );
+        assert_eq!(
+            format_line("msg .STRINGZ \"a; b, c\"").unwrap(),
+            format!("{:16}.STRINGZ \"a; b, c\"", "msg")
+        );
+    }
+
+    #[test]
+    pub fn comments_and_blank_lines() {
+        assert_eq!(format_line(";; header").unwrap(), ";; header");
+        assert_eq!(format_line("\t; note  ").unwrap(), format!("{:16}; note", ""));
+        assert_eq!(format_line(" \t ").unwrap(), "");
+    }
+
+    #[test]
+    pub fn idempotent() {
+        let source: Vec<String> = [
+            "; Counts down from 5",
+            "        .ORIG x3000",
+            "        and r0,r0,#0 ; clear",
+            "",
+            "a_very_long_label_name add R0, R0, b101",
+            "  ; indented comment",
+            "LOOP    BRZP LOOP",
+            "N .FILL xff",
+            ".END",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+
+        let once = format_source(&source).unwrap();
+        let twice = format_source(&once).unwrap();
+        assert_eq!(once, twice);
+        assert_eq!(once.len(), source.len());
+        assert!(once[4].starts_with("a_very_long_label_name ADD"));
+        assert!(once[6].contains("BRzp"));
+    }
+
+    #[test]
+    pub fn invalid_source() {
+        assert!(format_source(&[String::from("ADD R9, R0, #1")]).is_err());
+    }
+}
@@ -0,0 +1,677 @@
+use crate::archive::Archive;
+use crate::assemble::*;
+use crate::error::AsmblrErr;
+use crate::file_io::*;
+use std::collections::HashMap;
+
+/// An object file to be linked: its image, with the `.sym` and `.rel` files written beside it.
+pub struct ObjectModule {
+    pub name: String,
+    pub image: ExecutableImageIn,
+    pub symbols: Vec<Symbol>,
+    pub relocations: Vec<Relocation>,
+}
+
+impl ObjectModule {
+    /// Reads `path`, `path.sym` and `path.rel`.
+    pub fn load(path: &str) -> Result<Self, AsmblrErr> {
+        let load_error = |file: &str, e| {
+            AsmblrErr::new(None, format!("Unable to read '{file}', {e:?}"))
+                .link_error()
+                .clone()
+        };
+
+        let image = read_exectuable_img_from_file(path, None)
+            .map_err(|e| load_error(path, e))?;
+        let sym_path = format!("{path}.sym");
+        let symbols = read_symbols_from_file(&sym_path).map_err(|e| load_error(&sym_path, e))?;
+        let rel_path = format!("{path}.rel");
+        let relocations =
+            read_relocations_from_file(&rel_path).map_err(|e| load_error(&rel_path, e))?;
+
+        Ok(Self {
+            name: path.to_string(),
+            image,
+            symbols,
+            relocations,
+        })
+    }
+
+    fn address_of(&self, symbol: &Symbol) -> u16 {
+        self.image.origin.wrapping_add(symbol.rel_addr)
+    }
+}
+
+/// The name under which a symbol is linked. Labels are case-insensitive, as they are in the
+/// assembler by default, so `PRINT .IMPORT` is resolved by `print .EXPORT`.
+pub fn link_name(name: &str) -> String {
+    name.to_ascii_uppercase()
+}
+
+/// Imports of `modules` which none of them export.
+pub fn unresolved_imports(modules: &[ObjectModule]) -> Vec<String> {
+    let tables: Vec<(&str, &[Symbol])> = modules
+        .iter()
+        .map(|module| (module.name.as_str(), module.symbols.as_slice()))
+        .collect();
+
+    let mut unresolved: Vec<String> = Vec::new();
+    for (symbol, _) in missing_imports(&tables) {
+        if !unresolved.contains(&symbol) {
+            unresolved.push(symbol);
+        }
+    }
+    unresolved
+}
+
+/// Each import in the symbol tables of `modules` which none of them export, with the name of
+/// the module which imports it.
+pub fn missing_imports(modules: &[(&str, &[Symbol])]) -> Vec<(String, String)> {
+    let exported = |name: &str| {
+        modules.iter().any(|(_, symbols)| {
+            symbols
+                .iter()
+                .any(|s| s.status.is_export() && s.name.eq_ignore_ascii_case(name))
+        })
+    };
+
+    let mut missing = Vec::new();
+    for (module, symbols) in modules {
+        for symbol in symbols.iter() {
+            if matches!(symbol.status, SymbolStatus::Import) && !exported(&symbol.name) {
+                missing.push((symbol.name.clone(), module.to_string()));
+            }
+        }
+    }
+    missing
+}
+
+/// Each import in the symbol tables of `modules` which is declared as a different kind or size
+/// than the export of the name (see `check_import`).
+pub fn mismatched_imports(modules: &[(&str, &[Symbol])]) -> Vec<String> {
+    let mut mismatched = Vec::new();
+    for (importer, symbols) in modules {
+        for import in symbols.iter().filter(|s| matches!(s.status, SymbolStatus::Import)) {
+            let export = modules.iter().find_map(|(exporter, symbols)| {
+                symbols
+                    .iter()
+                    .find(|s| s.status.is_export() && s.name.eq_ignore_ascii_case(&import.name))
+                    .map(|export| (*exporter, export))
+            });
+            if let Some((exporter, export)) = export {
+                if let Err(msg) = check_import(import, importer, export, exporter) {
+                    mismatched.push(msg);
+                }
+            }
+        }
+    }
+    mismatched
+}
+
+/// Adds to `modules` the archive members which export their unresolved imports, then the
+/// members which resolve the imports of those members, and so on. Other members are left out.
+pub fn pull_archive_members(
+    modules: &mut Vec<ObjectModule>,
+    archives: &[(String, Archive)],
+) -> Result<(), AsmblrErr> {
+    let mut pulled: Vec<(usize, usize)> = Vec::new();
+    loop {
+        let needed = unresolved_imports(modules).iter().find_map(|name| {
+            archives.iter().enumerate().find_map(|(i, (_, archive))| {
+                archive
+                    .find(name)
+                    .map(|member| (i, member))
+                    .filter(|member| !pulled.contains(member))
+            })
+        });
+
+        let Some((i, member)) = needed else {
+            return Ok(());
+        };
+        let (archive_name, archive) = &archives[i];
+        match archive.module(archive_name, member) {
+            Ok(module) => modules.push(module),
+            Err(e) => {
+                return Err(AsmblrErr::new(
+                    None,
+                    format!("Unable to read member {member} of '{archive_name}', {e:?}"),
+                )
+                .link_error()
+                .clone())
+            }
+        }
+        pulled.push((i, member));
+    }
+}
+
+/// Links `modules`, each placed at its own origin, into one image which starts at the lowest
+/// origin, with the origin of the first module as its entry point. Every import is resolved against the exports of the other modules, and
+/// every relocation is patched. A `.WEAK` export is overridden by an `.EXPORT` of the same name,
+/// even for references from its own module, and is private in the linked image when it is.
+pub fn link(name: &str, mut modules: Vec<ObjectModule>) -> Result<ExecutableImageOut, Vec<AsmblrErr>> {
+    let link_error = |msg: String| AsmblrErr::new(None, msg).link_error().clone();
+    let mut errors = Vec::new();
+
+    if modules.is_empty() {
+        return Err(vec![link_error(String::from("No object files to link."))]);
+    }
+
+    let images: Vec<ExecutableImageIn> = modules
+        .iter()
+        .map(|module| ExecutableImageIn {
+            origin: module.image.origin,
+            data: module.image.data.clone(),
+        })
+        .collect();
+    if let Some(((min1, max1), (min2, max2))) = ExecutableImageIn::images_overlap(&images) {
+        return Err(vec![link_error(format!(
+            "Object files overlap: [x{min1:04x}, x{max1:04x}] and [x{min2:04x}, x{max2:04x}]."
+        ))]);
+    }
+
+    let entry = modules[0].image.origin;
+    let origin = modules.iter().map(|module| module.image.origin).min().unwrap_or(entry);
+
+    let exports = match resolve_exports(&modules) {
+        Ok(exports) => exports,
+        Err(mut duplicates) => {
+            errors.append(&mut duplicates);
+            HashMap::new()
+        }
+    };
+
+    // (module index, relative address, patched word)
+    let mut patches = Vec::new();
+    for (i, module) in modules.iter().enumerate() {
+        for symbol in module.symbols.iter().filter(|symbol| matches!(symbol.status, SymbolStatus::Import)) {
+            let Some((_, exporter)) = exports.get(&link_name(&symbol.name)) else {
+                errors.push(link_error(format!(
+                    "Unable to resolve import for symbol '{}' in '{}'",
+                    symbol.name, module.name
+                )));
+                continue;
+            };
+            let export = modules
+                .iter()
+                .filter(|other| other.name == *exporter)
+                .flat_map(|other| &other.symbols)
+                .find(|other| other.name.eq_ignore_ascii_case(&symbol.name) && other.status.is_export());
+            if let Some(export) = export {
+                if let Err(msg) = check_import(symbol, &module.name, export, exporter) {
+                    errors.push(link_error(msg));
+                }
+            }
+        }
+
+        for relocation in &module.relocations {
+            let target = match module.symbols.iter().find(|s| s.name == relocation.symbol) {
+                Some(symbol) if matches!(symbol.status, SymbolStatus::Import) => {
+                    match exports.get(&link_name(&symbol.name)) {
+                        Some((addr, _)) => *addr,
+                        None => continue, // Reported above
+                    }
+                }
+                // A module's own `.WEAK` definition may be overridden by another module's
+                Some(symbol) if symbol.status.is_export() => match exports.get(&link_name(&symbol.name)) {
+                    Some((addr, _)) => *addr,
+                    None => module.address_of(symbol),
+                },
+                Some(symbol) => module.address_of(symbol),
+                None => {
+                    errors.push(link_error(format!(
+                        "Relocation at x{:04x} in '{}' refers to unknown symbol '{}'.",
+                        module.image.origin.wrapping_add(relocation.rel_addr),
+                        module.name,
+                        relocation.symbol
+                    )));
+                    continue;
+                }
+            };
+
+            let addr = module.image.origin.wrapping_add(relocation.rel_addr);
+            let word = match module.image.data.get(relocation.rel_addr as usize) {
+                Some(word) => *word,
+                None => {
+                    errors.push(link_error(format!(
+                        "Relocation at x{addr:04x} is outside of '{}'.",
+                        module.name
+                    )));
+                    continue;
+                }
+            };
+            match relocation.kind.patch(word, addr, target) {
+                Ok(word) => patches.push((i, relocation.rel_addr, word)),
+                Err(msg) => errors.push(link_error(format!(
+                    "Unable to refer to '{}' from '{}': {msg}",
+                    relocation.symbol, module.name
+                ))),
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    // Module whose definition each exported name resolved to
+    let definitions: HashMap<String, String> = exports
+        .iter()
+        .map(|(name, (_, module))| (name.to_string(), module.to_string()))
+        .collect();
+    for (i, rel_addr, word) in patches {
+        modules[i].image.data[rel_addr as usize] = word;
+    }
+
+    let mut img = ExecutableImageOut::new(name.to_string());
+    img.origin = origin;
+    img.entry = (entry != origin).then_some(entry);
+    for module in &modules {
+        let offset = module.image.origin.wrapping_sub(origin);
+        for (i, word) in module.image.data.iter().enumerate() {
+            img.data.push(MemoryWrite {
+                rel_addr: offset.wrapping_add(i as u16),
+                value: *word,
+            });
+        }
+        for symbol in &module.symbols {
+            if matches!(symbol.status, SymbolStatus::Import) {
+                continue;
+            }
+            let abs_addr = module.address_of(symbol);
+            let overridden = matches!(symbol.status, SymbolStatus::Weak)
+                && definitions.get(&link_name(&symbol.name)) != Some(&module.name);
+            img.symbol_table.push(Symbol {
+                rel_addr: abs_addr.wrapping_sub(origin),
+                abs_addr,
+                status: if overridden { SymbolStatus::Private } else { symbol.status.clone() },
+                ..symbol.clone()
+            });
+        }
+    }
+    Ok(img)
+}
+
+/// Fails if `import`, as declared by `importer` with `.IMPORT DATA 16` or the like, doesn't match
+/// the kind and size of `export` from `exporter`. A symbol of size 0 is of unknown kind, e.g. an
+/// import without a declaration or a symbol read from a legacy `.sym` file, and matches any other.
+/// A constant is exported as a word of data, e.g. `TEN .EXPORT .FILL #10`.
+pub fn check_import(import: &Symbol, importer: &str, export: &Symbol, exporter: &str) -> Result<(), String> {
+    if import.size_in_words == 0 || export.size_in_words == 0 {
+        return Ok(());
+    }
+    // A routine's size is however long its code is, which the importer has no say in
+    let sizes_match = matches!(import.kind, SymbolKind::Code) || import.size_in_words == export.size_in_words;
+    let kinds_match = import.kind == export.kind
+        || matches!((import.kind, export.kind), (SymbolKind::Constant, SymbolKind::Data));
+    if kinds_match && sizes_match {
+        return Ok(());
+    }
+    Err(format!(
+        "Symbol '{}' is imported by '{importer}' as {}, but '{exporter}' exports {}.",
+        import.name,
+        describe_symbol(import),
+        describe_symbol(export)
+    ))
+}
+
+/// e.g. `data (16 words)`
+fn describe_symbol(symbol: &Symbol) -> String {
+    match (symbol.kind, symbol.size_in_words) {
+        (SymbolKind::Code, _) => String::from("code"),
+        (kind, 1) => format!("{} (1 word)", kind.name()),
+        (kind, size) => format!("{} ({size} words)", kind.name()),
+    }
+}
+
+/// The address and module of the definition each exported name resolves to: its `.EXPORT`, or
+/// else its first `.WEAK` export, by `link_name`. Fails if two modules `.EXPORT` the same name.
+pub fn resolve_exports(modules: &[ObjectModule]) -> Result<HashMap<String, (u16, &str)>, Vec<AsmblrErr>> {
+    let mut exports: HashMap<String, (u16, &str, bool)> = HashMap::new();
+    let mut errors = Vec::new();
+    for module in modules {
+        for symbol in module.symbols.iter().filter(|symbol| symbol.status.is_export()) {
+            let strong = matches!(symbol.status, SymbolStatus::Export);
+            match exports.get(&link_name(&symbol.name)) {
+                Some((_, other, true)) if strong => errors.push(
+                    AsmblrErr::new(
+                        None,
+                        format!("Symbol '{}' is exported by both '{other}' and '{}'.", symbol.name, module.name),
+                    )
+                    .link_error()
+                    .clone(),
+                ),
+                Some((_, _, other_strong)) if *other_strong || !strong => {}
+                _ => {
+                    exports.insert(link_name(&symbol.name), (module.address_of(symbol), &module.name, strong));
+                }
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(exports
+        .into_iter()
+        .map(|(name, (addr, module, _))| (name, (addr, module)))
+        .collect())
+}
+
+/// Moves `segments`, an image assembled at the origin of its lowest segment, to start at
+/// `load_addr` instead. Words holding an address in the image (`.FILL LABEL`, the `ABS16`
+/// relocations to its own symbols) are moved with it; PC-relative instructions are kept as they
+/// are, except those which refer to an import, which stays where it is. Without `relocations`
+/// the image is refused if any of its data (as given by the sizes of its data `symbols`) could
+/// be an address in it, or if `symbols` doesn't say which words are data.
+pub fn relocate_image(
+    name: &str,
+    segments: &mut [ExecutableImageIn],
+    load_addr: u16,
+    relocations: Option<&[Relocation]>,
+    symbols: &[Symbol],
+) -> Result<(), String> {
+    let Some(origin) = segments.iter().map(|segment| segment.origin).min() else {
+        return Ok(());
+    };
+    let mut image = SparseImage::from_segments(segments);
+    let last = image.iter().last().map(|(addr, _)| addr).unwrap_or(origin);
+    if load_addr as u32 + (last - origin) as u32 > 0xFFFF {
+        return Err(format!("'{name}' does not fit in memory at x{load_addr:04x}."));
+    }
+    let delta = load_addr.wrapping_sub(origin);
+
+    let Some(relocations) = relocations else {
+        // Only data can hold an address; an instruction such as `ST R0, X` may just look like one
+        if !symbols.iter().any(|symbol| symbol.size_in_words > 0) {
+            return Err(format!(
+                "'{name}' has no relocation data, nor a symbol table saying which of its words are data; it can only be loaded at x{origin:04x}."
+            ));
+        }
+        let data = symbols
+            .iter()
+            .filter(|symbol| matches!(symbol.kind, SymbolKind::Data) && !matches!(symbol.status, SymbolStatus::Import))
+            .flat_map(|symbol| (0..symbol.size_in_words).map(|i| symbol.abs_addr.wrapping_add(i)));
+        for addr in data {
+            match image.get(addr) {
+                Some(word) if word >= origin && word <= last => {
+                    return Err(format!(
+                        "'{name}' has no relocation data, and x{addr:04x} holds x{word:04x}, which may be an address in it; it can only be loaded at x{origin:04x}."
+                    ))
+                }
+                _ => {}
+            }
+        }
+        move_segments(segments, &image, delta);
+        return Ok(());
+    };
+
+    for relocation in relocations {
+        let addr = origin.wrapping_add(relocation.rel_addr);
+        let Some(word) = image.get(addr) else {
+            return Err(format!("Relocation at x{addr:04x} is outside of '{name}'."));
+        };
+        let imported = symbols
+            .iter()
+            .any(|s| s.name == relocation.symbol && matches!(s.status, SymbolStatus::Import));
+        let patched = match (relocation.kind, imported) {
+            (RelocationKind::Absolute, false) => word.wrapping_add(delta),
+            (RelocationKind::Absolute, true) => word,
+            (_, false) => word,
+            (kind, true) => {
+                let bits = if kind == RelocationKind::PcOffset11 { 11 } else { 9 };
+                let offset = ((word << (16 - bits)) as i16) >> (16 - bits);
+                let target = addr.wrapping_add(1).wrapping_add(offset as u16);
+                kind.patch(word, addr.wrapping_add(delta), target)
+                    .map_err(|msg| format!("Unable to refer to '{}' from '{name}': {msg}", relocation.symbol))?
+            }
+        };
+        image.insert(addr, patched);
+    }
+    move_segments(segments, &image, delta);
+    Ok(())
+}
+
+/// Moves each of `segments` by `delta`, with its words from `image`.
+fn move_segments(segments: &mut [ExecutableImageIn], image: &SparseImage, delta: u16) {
+    for segment in segments.iter_mut() {
+        for (i, word) in segment.data.iter_mut().enumerate() {
+            *word = image.get(segment.origin.wrapping_add(i as u16)).unwrap_or(*word);
+        }
+        segment.origin = segment.origin.wrapping_add(delta);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn module(name: &str, source: &str) -> ObjectModule {
+        let mut asm = Assembler::new(name);
+        asm.relocatable = true;
+        asm.load_str(source);
+        let img = match asm.assemble(vec![]) {
+            Ok(img) => img,
+            Err(errors) => panic!("{errors:?}"),
+        };
+
+        let writes: Vec<MemoryWrite> = img
+            .instructions
+            .iter()
+            .chain(img.data.iter())
+            .map(|w| MemoryWrite { rel_addr: w.rel_addr, value: w.value })
+            .collect();
+        ObjectModule {
+            name: name.to_string(),
+            image: ExecutableImageIn::from_writes(img.origin, &writes),
+            symbols: img.symbol_table,
+            relocations: img.relocations,
+        }
+    }
+
+    fn words(img: &ExecutableImageOut) -> Vec<(u16, u16)> {
+        img.data.iter().map(|w| (w.rel_addr, w.value)).collect()
+    }
+
+    const MAIN: &str = ".ORIG x3000
+        PRINT .IMPORT
+        JSR PRINT
+        HALT
+        PTR .FILL PRINT
+        .END";
+
+    const LIB: &str = ".ORIG x3100
+        PRINT .EXPORT RET
+        .END";
+
+    #[test]
+    pub fn resolves_imports() {
+        let img = link("prog.obj", vec![module("main.obj", MAIN), module("lib.obj", LIB)]).unwrap();
+
+        assert_eq!(img.origin, 0x3000);
+        assert_eq!(
+            words(&img),
+            vec![(0, 0x48FF), (1, 0xF025), (2, 0x3100), (0x100, 0xC1C0)]
+        );
+        let print = img.symbol_table.iter().find(|s| s.name == "PRINT").unwrap();
+        assert_eq!((print.rel_addr, print.abs_addr), (0x100, 0x3100));
+        assert!(matches!(print.status, SymbolStatus::Export));
+        assert_eq!(img.symbol_table.len(), 2);
+    }
+
+    #[test]
+    pub fn missing_imports_name_the_importer() {
+        let main = module("main.obj", MAIN);
+        let lib = module("lib.obj", LIB);
+        let tables = |modules: &[&ObjectModule]| -> Vec<(String, String)> {
+            let tables: Vec<(&str, &[Symbol])> =
+                modules.iter().map(|m| (m.name.as_str(), m.symbols.as_slice())).collect();
+            missing_imports(&tables)
+        };
+
+        assert_eq!(tables(&[&main]), vec![(String::from("PRINT"), String::from("main.obj"))]);
+        assert!(tables(&[&main, &lib]).is_empty());
+        assert!(tables(&[&lib]).is_empty());
+    }
+
+    #[test]
+    pub fn link_errors() {
+        // Unresolved import
+        assert!(link("prog.obj", vec![module("main.obj", MAIN)]).is_err());
+        // Duplicate export
+        let lib2 = LIB.replace("x3100", "x3200");
+        assert!(link(
+            "prog.obj",
+            vec![module("main.obj", MAIN), module("lib.obj", LIB), module("lib2.obj", &lib2)]
+        )
+        .is_err());
+        // Overlap
+        let lib_overlapping = LIB.replace("x3100", "x3001");
+        assert!(link(
+            "prog.obj",
+            vec![module("main.obj", MAIN), module("lib.obj", &lib_overlapping)]
+        )
+        .is_err());
+        // Out of range of JSR
+        let lib_far = LIB.replace("x3100", "x4000");
+        assert!(link("prog.obj", vec![module("main.obj", MAIN), module("lib.obj", &lib_far)]).is_err());
+    }
+
+    #[test]
+    pub fn links_below_the_first_module_ignoring_case() {
+        let lib = LIB.replace("x3100", "x2F00").replace("PRINT .EXPORT", "print .EXPORT");
+        let img = link("prog.obj", vec![module("main.obj", MAIN), module("lib.obj", &lib)]).unwrap();
+        assert_eq!((img.origin, img.entry), (0x2F00, Some(0x3000)));
+        assert_eq!(words(&img), vec![(0x100, 0x4EFF), (0x101, 0xF025), (0x102, 0x2F00), (0, 0xC1C0)]);
+    }
+
+    #[test]
+    pub fn checks_import_kinds() {
+        const MAIN: &str = ".ORIG x3000
+            BUF .IMPORT DATA 16
+            PRINT .IMPORT CODE
+            LEA R0, BUF
+            JSR PRINT
+            HALT
+            .END";
+        let lib = |size: u16| {
+            module(
+                "lib.obj",
+                &format!(".ORIG x3100\nBUF .EXPORT .BLKW {size}\nPRINT .EXPORT RET\n.END"),
+            )
+        };
+
+        let img = link("prog.obj", vec![module("main.obj", MAIN), lib(16)]).unwrap();
+        let buf = img.symbol_table.iter().find(|s| s.name == "BUF").unwrap();
+        assert_eq!((buf.kind, buf.size_in_words, buf.abs_addr), (SymbolKind::Data, 16, 0x3100));
+        assert_eq!(words(&img)[..2], [(0, 0xE0FF), (1, 0x490E)]);
+
+        let Err(errors) = link("prog.obj", vec![module("main.obj", MAIN), lib(8)]) else {
+            panic!("Linked a 16-word import to an 8-word export");
+        };
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].msg,
+            "Symbol 'BUF' is imported by 'main.obj' as data (16 words), but 'lib.obj' exports data (8 words)."
+        );
+
+        // Without a declaration the import can be anything
+        let untyped = MAIN.replace(" DATA 16", "").replace(" CODE", "");
+        assert!(link("prog.obj", vec![module("main.obj", &untyped), lib(8)]).is_ok());
+
+        // A constant is a word of data
+        const READER: &str = ".ORIG x3000\nTEN .IMPORT CONSTANT\nLD R0, TEN\nHALT\n.END";
+        let ten = |source: &str| module("ten.obj", &format!(".ORIG x3100\n{source}\n.END"));
+        let img = link("prog.obj", vec![module("main.obj", READER), ten("TEN .EXPORT .FILL #10")]).unwrap();
+        assert_eq!(words(&img)[..1], [(0, 0x20FF)]);
+        let Err(errors) = link("prog.obj", vec![module("main.obj", READER), ten("TEN .EXPORT .BLKW 2")]) else {
+            panic!("Linked a constant import to a 2-word export");
+        };
+        assert_eq!(
+            errors[0].msg,
+            "Symbol 'TEN' is imported by 'main.obj' as constant (1 word), but 'ten.obj' exports data (2 words)."
+        );
+    }
+
+    #[test]
+    pub fn weak_exports() {
+        const MAIN: &str = ".ORIG x3000\nHANDLER .IMPORT\nJSR HANDLER\nHALT\n.END";
+        const DEFAULT: &str = ".ORIG x3100\nHANDLER .WEAK RET\n.END";
+        const CUSTOM: &str = ".ORIG x3200\nHANDLER .EXPORT RET\n.END";
+        fn handler(img: &ExecutableImageOut) -> Vec<(u16, &str)> {
+            img.symbol_table
+                .iter()
+                .filter(|s| s.name == "HANDLER")
+                .map(|s| (s.abs_addr, s.status.name()))
+                .collect()
+        }
+
+        // The default handler, unless a module exports its own
+        let img = link("prog.obj", vec![module("main.obj", MAIN), module("lib.obj", DEFAULT)]).unwrap();
+        assert_eq!(img.data[0].value, 0x48FF);
+        assert_eq!(handler(&img), vec![(0x3100, "weak")]);
+
+        let modules = vec![module("main.obj", MAIN), module("lib.obj", DEFAULT), module("custom.obj", CUSTOM)];
+        let img = link("prog.obj", modules).unwrap();
+        assert_eq!(img.data[0].value, 0x49FF);
+        assert_eq!(handler(&img), vec![(0x3100, "private"), (0x3200, "export")]);
+
+        // Calls from within the weak module go to the override too
+        const CALLER: &str = ".ORIG x3100\nJSR HANDLER\nRET\nHANDLER .WEAK RET\n.END";
+        let img = link("prog.obj", vec![module("lib.obj", CALLER), module("custom.obj", CUSTOM)]).unwrap();
+        assert_eq!(img.data[0].value, 0x48FF);
+        let img = link("prog.obj", vec![module("lib.obj", CALLER)]).unwrap();
+        assert_eq!(img.data[0].value, 0x4801);
+
+        // The first of several weak definitions
+        let default2 = DEFAULT.replace("x3100", "x3300");
+        let img = link("prog.obj", vec![module("main.obj", MAIN), module("lib.obj", DEFAULT), module("lib2.obj", &default2)]).unwrap();
+        assert_eq!(img.data[0].value, 0x48FF);
+
+        // Two strong definitions are an error naming both modules
+        let custom2 = CUSTOM.replace("x3200", "x3300");
+        let modules = vec![module("main.obj", MAIN), module("custom.obj", CUSTOM), module("custom2.obj", &custom2)];
+        let errors = link("prog.obj", modules).err().unwrap();
+        assert!(errors.iter().any(|e| format!("{e:?}").contains("'custom.obj' and 'custom2.obj'")));
+    }
+
+    #[test]
+    pub fn relocates_at_load_time() {
+        const PROG: &str = ".ORIG x3000
+            PRINT .IMPORT
+            JSR PRINT
+            LEA R0, MSG
+            HALT
+            MSG .FILL #1
+            PTR .FILL MSG
+            EXT .FILL PRINT
+            .END";
+        let prog = module("prog.obj", PROG);
+        let segments = || vec![ExecutableImageIn { origin: 0x3000, data: prog.image.data.clone() }];
+
+        // The import (left at x3001 by the assembler) stays where it is, and MSG moves
+        let mut moved = segments();
+        relocate_image("prog.obj", &mut moved, 0x3010, Some(&prog.relocations), &prog.symbols).unwrap();
+        assert_eq!(moved[0].origin, 0x3010);
+        assert_eq!(moved[0].data, vec![0x4FF0, 0xE001, 0xF025, 1, 0x3013, 0]);
+
+        // An import which would then be out of range
+        let mut moved = segments();
+        assert!(relocate_image("prog.obj", &mut moved, 0x5000, Some(&prog.relocations), &prog.symbols).is_err());
+
+        // Without relocations, only an image with nothing like an address in its data can move
+        let mut moved = segments();
+        assert!(relocate_image("prog.obj", &mut moved, 0x3010, None, &prog.symbols).is_err());
+        let store = module("store.obj", ".ORIG x3000\nST R0, VALUE\nHALT\nVALUE .FILL #5\n.END");
+        assert_eq!(store.image.data[0], 0x3001);
+        let mut moved = vec![ExecutableImageIn { origin: 0x3000, data: store.image.data.clone() }];
+        relocate_image("store.obj", &mut moved, 0x5000, None, &store.symbols).unwrap();
+        assert_eq!((moved[0].origin, moved[0].data.clone()), (0x5000, vec![0x3001, 0xF025, 5]));
+        // Nor without a symbol table saying which words are data
+        let mut moved = vec![ExecutableImageIn { origin: 0x3000, data: vec![0x1021, 0xF025] }];
+        assert!(relocate_image("prog.obj", &mut moved, 0x5000, None, &[]).is_err());
+
+        // Past the end of memory
+        let mut moved = segments();
+        assert!(relocate_image("prog.obj", &mut moved, 0xFFFE, Some(&prog.relocations), &prog.symbols).is_err());
+    }
+}
@@ -0,0 +1,301 @@
+use crate::assemble::SymbolStatus;
+use crate::error::AsmblrErr;
+use crate::linker::ObjectModule;
+use std::path::Path;
+
+/// A named range of memory which modules are placed into.
+#[derive(Clone, Debug)]
+pub struct MemoryRegion {
+    pub name: String,
+    pub origin: u16,
+    pub size: u32,
+    /// Any of `r`, `w` and `x`.
+    pub permissions: String,
+}
+
+impl MemoryRegion {
+    fn end(&self) -> u32 {
+        self.origin as u32 + self.size
+    }
+
+    fn contains(&self, addr: u16) -> bool {
+        addr >= self.origin && (addr as u32) < self.end()
+    }
+}
+
+/// Where the linker places each module, read from a script such as
+///
+/// ```text
+/// ; name  origin  size   permissions
+/// REGION CODE x3000 x1000 rx
+/// REGION LIB  x4000 x1000 rx
+/// REGION DATA xA000 x2000 rw
+/// ; module (file name, or * for any other)  region
+/// PLACE main.obj CODE
+/// PLACE table.obj DATA
+/// PLACE * LIB
+/// ```
+///
+/// Modules are packed into their region in the order they are linked; a module which no `PLACE`
+/// matches stays at its own origin.
+#[derive(Clone, Debug, Default)]
+pub struct LinkerScript {
+    pub regions: Vec<MemoryRegion>,
+    pub placements: Vec<(String, String)>,
+}
+
+impl LinkerScript {
+    pub fn parse(lines: &[String]) -> Result<Self, Vec<AsmblrErr>> {
+        let mut script = Self::default();
+        let mut errors = Vec::new();
+
+        for (i, line) in lines.iter().enumerate() {
+            let line_number = Some(i as u16 + 1);
+            let code = line.split(';').next().unwrap_or_default();
+            let fields: Vec<&str> = code.split_whitespace().collect();
+            match fields.as_slice() {
+                [] => {}
+                [keyword, name, origin, size, permissions] if keyword.eq_ignore_ascii_case("REGION") => {
+                    let (origin, size) = match (parse_number(origin), parse_number(size)) {
+                        (Some(origin), Some(size)) if size > 0 && origin <= 0xFFFF && origin + size <= 0x10000 => {
+                            (origin as u16, size)
+                        }
+                        _ => {
+                            errors.push(AsmblrErr::new(
+                                line_number,
+                                format!("Region '{name}' is not within memory (x0000 to xFFFF)."),
+                            ));
+                            continue;
+                        }
+                    };
+                    if !permissions.chars().all(|c| "rwx".contains(c)) {
+                        errors.push(AsmblrErr::new(
+                            line_number,
+                            format!("Invalid permissions '{permissions}', expected any of 'rwx'."),
+                        ));
+                    }
+                    if script.region(name).is_some() {
+                        errors.push(AsmblrErr::new(line_number, format!("Region '{name}' is already defined.")));
+                    }
+                    script.regions.push(MemoryRegion {
+                        name: name.to_string(),
+                        origin,
+                        size,
+                        permissions: permissions.to_string(),
+                    });
+                }
+                [keyword, module, region] if keyword.eq_ignore_ascii_case("PLACE") => {
+                    if script.region(region).is_none() {
+                        errors.push(AsmblrErr::new(line_number, format!("Undefined region '{region}'.")));
+                    }
+                    script.placements.push((module.to_string(), region.to_string()));
+                }
+                _ => errors.push(AsmblrErr::new(
+                    line_number,
+                    String::from("Expected 'REGION name origin size permissions' or 'PLACE module region'."),
+                )),
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(script)
+    }
+
+    pub fn region(&self, name: &str) -> Option<&MemoryRegion> {
+        self.regions.iter().find(|region| region.name == name)
+    }
+
+    /// Moves each module matched by a `PLACE` into its region, after the modules placed there
+    /// before it. The first module is the entry point, so its region has to be executable.
+    pub fn place(&self, modules: &mut [ObjectModule]) -> Result<(), Vec<AsmblrErr>> {
+        let link_error = |msg: String| AsmblrErr::new(None, msg).link_error().clone();
+        let mut errors = Vec::new();
+        // Next free address of each region
+        let mut next: Vec<u32> = self.regions.iter().map(|region| region.origin as u32).collect();
+
+        for (i, module) in modules.iter_mut().enumerate() {
+            let region_name = match self.placements.iter().find(|(pattern, _)| matches_module(pattern, &module.name)) {
+                Some((_, region)) => region,
+                None => continue,
+            };
+            let r = self.regions.iter().position(|region| region.name == *region_name).unwrap();
+            let region = &self.regions[r];
+
+            let size = module.image.data.len() as u32;
+            if next[r] + size > region.end() {
+                errors.push(link_error(format!(
+                    "Region '{}' (x{:04x} to x{:04x}) overflows by {} words placing '{}'.",
+                    region.name,
+                    region.origin,
+                    region.end() - 1,
+                    next[r] + size - region.end(),
+                    module.name
+                )));
+                continue;
+            }
+            if i == 0 && !region.permissions.contains('x') {
+                errors.push(link_error(format!(
+                    "'{}' is the entry point, but region '{}' is not executable ('{}').",
+                    module.name, region.name, region.permissions
+                )));
+            }
+            module.image.origin = next[r] as u16;
+            next[r] += size;
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(())
+    }
+
+    /// The contents of a `.map` file: each region with how much of it is used, then where each
+    /// module and symbol ended up.
+    pub fn map(&self, modules: &[ObjectModule]) -> Vec<String> {
+        let region_of = |addr: u16| {
+            self.regions
+                .iter()
+                .find(|region| region.contains(addr))
+                .map(|region| region.name.as_str())
+                .unwrap_or("-")
+        };
+
+        let mut lines = vec![format!(
+            ";{:15}\t{:5}\t{:5}\t{:>6}\t{:>6}\t{}",
+            "Region", "Start", "End", "Size", "Used", "Perm"
+        )];
+        for region in &self.regions {
+            let used: usize = modules
+                .iter()
+                .filter(|module| region.contains(module.image.origin))
+                .map(|module| module.image.data.len())
+                .sum();
+            lines.push(format!(
+                "{:16}\tx{:04X}\tx{:04X}\t{:6}\t{:6}\t{}",
+                region.name,
+                region.origin,
+                region.end() - 1,
+                region.size,
+                used,
+                region.permissions
+            ));
+        }
+
+        lines.push(String::new());
+        lines.push(format!(";{:23}\t{:5}\t{:5}\t{}", "Module", "Start", "End", "Region"));
+        for module in modules {
+            let end = module.image.origin.wrapping_add((module.image.data.len() as u16).wrapping_sub(1));
+            lines.push(format!(
+                "{:24}\tx{:04X}\tx{:04X}\t{}",
+                module.name,
+                module.image.origin,
+                end,
+                region_of(module.image.origin)
+            ));
+        }
+
+        lines.push(String::new());
+        lines.push(format!(";{:23}\t{:5}\t{:7}\t{}", "Symbol", "Addr", "Scope", "Module"));
+        let mut symbols = Vec::new();
+        for module in modules {
+            for symbol in &module.symbols {
+                if !matches!(symbol.status, SymbolStatus::Import) {
+                    symbols.push((module.image.origin.wrapping_add(symbol.rel_addr), symbol, &module.name));
+                }
+            }
+        }
+        symbols.sort_by_key(|(addr, _, _)| *addr);
+        for (addr, symbol, module) in symbols {
+            lines.push(format!("{:24}\tx{addr:04X}\t{:7}\t{module}", symbol.name, symbol.status.name()));
+        }
+        lines
+    }
+}
+
+/// `x3000` (hex), `#4096` or `4096` (decimal).
+fn parse_number(text: &str) -> Option<u32> {
+    match text.strip_prefix(['x', 'X']) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.trim_start_matches('#').parse().ok(),
+    }
+}
+
+/// A pattern matches a module by its path, its file name, or, for an archive member such as
+/// `libstd.lib(print.obj)`, the member name. `*` matches every module.
+fn matches_module(pattern: &str, module: &str) -> bool {
+    if pattern == "*" || pattern == module {
+        return true;
+    }
+    let member = module
+        .strip_suffix(')')
+        .and_then(|m| m.rsplit_once('('))
+        .map(|(_, member)| member);
+    let file_name = Path::new(module).file_name().map(|name| name.to_string_lossy());
+    member == Some(pattern) || file_name.as_deref() == Some(pattern)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assemble::ExecutableImageIn;
+
+    fn script(text: &str) -> Result<LinkerScript, Vec<AsmblrErr>> {
+        LinkerScript::parse(&text.lines().map(|l| l.to_string()).collect::<Vec<_>>())
+    }
+
+    fn module(name: &str, origin: u16, size: usize) -> ObjectModule {
+        ObjectModule {
+            name: name.to_string(),
+            image: ExecutableImageIn { origin, data: vec![0; size] },
+            symbols: vec![],
+            relocations: vec![],
+        }
+    }
+
+    const SCRIPT: &str = "; memory map
+        REGION CODE x3000 x1000 rx
+        REGION LIB  x4000 #16 rx ; small
+        REGION DATA xA000 x2000 rw
+        PLACE main.obj CODE
+        PLACE table.obj DATA
+        PLACE * LIB";
+
+    #[test]
+    pub fn places_modules_in_regions() {
+        let script = script(SCRIPT).unwrap();
+        let mut modules = vec![
+            module("build/main.obj", 0x3000, 4),
+            module("a.obj", 0x3000, 10),
+            module("table.obj", 0x3000, 2),
+            module("libstd.lib(b.obj)", 0x3000, 6),
+        ];
+        script.place(&mut modules).unwrap();
+        let origins: Vec<u16> = modules.iter().map(|m| m.image.origin).collect();
+        assert_eq!(origins, vec![0x3000, 0x4000, 0xA000, 0x400A]);
+
+        let map = script.map(&modules);
+        assert!(map.iter().any(|l| l.starts_with("LIB") && l.contains("16\t    16\trx")));
+        assert!(map.iter().any(|l| l.starts_with("libstd.lib(b.obj)") && l.contains("x400A\tx400F\tLIB")));
+    }
+
+    #[test]
+    pub fn region_overflow_and_permissions() {
+        let script = script(SCRIPT).unwrap();
+        let mut modules = vec![module("main.obj", 0x3000, 4), module("a.obj", 0x3000, 17)];
+        assert!(script.place(&mut modules).is_err());
+
+        let mut modules = vec![module("table.obj", 0x3000, 4)];
+        assert!(script.place(&mut modules).is_err());
+    }
+
+    #[test]
+    pub fn invalid_scripts() {
+        assert!(script("REGION CODE x3000 x1000").is_err());
+        assert!(script("REGION CODE xF000 x2000 rx").is_err());
+        assert!(script("REGION CODE x3000 x1000 rq").is_err());
+        assert!(script("PLACE main.obj CODE").is_err());
+        assert!(script("REGION A x3000 x10 r\nREGION A x4000 x10 r").is_err());
+    }
+}
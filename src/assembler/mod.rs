@@ -1,2 +1,9 @@
+pub mod archive;
 pub mod assemble;
+pub mod dead_code;
+pub mod diff;
+pub mod formatter;
+pub mod linker;
+pub mod linker_script;
+pub mod objdump;
 pub mod tokenizer;
@@ -0,0 +1,200 @@
+use crate::assemble::*;
+use crate::file_io::ObjectHeader;
+
+/// The header of `lc3-asm-vm objdump`: where the image starts, and each segment it writes,
+/// after the fields of the `.obj` header if there is one.
+pub fn headers(name: &str, segments: &[ExecutableImageIn], header: Option<&ObjectHeader>) -> Vec<String> {
+    let words: usize = segments.iter().map(|segment| segment.data.len()).sum();
+    let mut lines = vec![format!("{name}:")];
+    if let Some(header) = header {
+        let [major, minor, patch] = header.assembler_version;
+        lines.push(format!("Header:\tversion {}, {:?}-endian, CRC {:08X}", header.version, header.endian, header.crc));
+        lines.push(format!("Built:\t{} (lc3-asm-vm {major}.{minor}.{patch})", header.timestamp));
+        lines.push(format!("Entry:\tx{:04X}", header.entry));
+    }
+    if let Some(first) = segments.iter().min_by_key(|segment| segment.origin) {
+        lines.push(format!("Origin:\tx{:04X}", first.origin));
+    }
+    lines.push(format!("Words:\t{words}"));
+    let segments = coalesce(segments);
+    lines.push(format!("Segments:\t{}", segments.len()));
+    for segment in &segments {
+        lines.push(format!(
+            "\tx{:04X} - x{:04X}\t{:5} words",
+            segment.origin,
+            end_of(segment),
+            segment.data.len()
+        ));
+    }
+    lines
+}
+
+/// The symbol table, in address order.
+pub fn symbols(symbols: &[Symbol]) -> Vec<String> {
+    let mut sorted: Vec<&Symbol> = symbols.iter().collect();
+    sorted.sort_by_key(|symbol| (matches!(symbol.status, SymbolStatus::Import), symbol.abs_addr));
+
+    let mut lines = vec![format!(";{:5}\t{:7}\t{:8}\t{:>5}\t{}", "Addr", "Scope", "Kind", "Size", "Symbol name")];
+    for symbol in sorted {
+        let addr = match symbol.status {
+            SymbolStatus::Import => String::from("-----"),
+            _ => format!("x{:04X}", symbol.abs_addr),
+        };
+        lines.push(format!(
+            "{addr}\t{:7}\t{:8}\t{:5}\t{}",
+            symbol.status.name(),
+            symbol.kind.name(),
+            symbol.size_in_words,
+            symbol.name
+        ));
+    }
+    lines
+}
+
+/// Each word of `segments` (within `range`, inclusive, if given) with its address, hex and
+/// binary, and the instruction it decodes to. Branch targets are shown by name if `symbols` has
+/// one, and words covered by a data or constant symbol are shown as `.FILL`.
+pub fn disassemble(segments: &[ExecutableImageIn], symbols: &[Symbol], range: Option<(u16, u16)>) -> Vec<String> {
+    let symbols: Vec<Symbol> = symbols
+        .iter()
+        .filter(|symbol| !matches!(symbol.status, SymbolStatus::Import))
+        .cloned()
+        .collect();
+    let is_data = |addr: u16| {
+        symbols.iter().any(|symbol| {
+            !matches!(symbol.kind, SymbolKind::Code)
+                && addr >= symbol.abs_addr
+                && (addr as u32) < symbol.abs_addr as u32 + symbol.size_in_words as u32
+        })
+    };
+
+    let mut lines = Vec::new();
+    for segment in coalesce(segments) {
+        for (i, word) in segment.data.iter().enumerate() {
+            let addr = segment.origin.wrapping_add(i as u16);
+            if let Some((start, end)) = range {
+                if addr < start || addr > end {
+                    continue;
+                }
+            }
+            for symbol in symbols.iter().filter(|symbol| symbol.abs_addr == addr) {
+                lines.push(format!("{}:", symbol.name));
+            }
+            let text = if is_data(addr) {
+                format!(".FILL\tx{word:04X}")
+            } else {
+                InstructionSet::dissasemble_memory(*word, Some(addr.wrapping_add(1)), Some(&symbols), None)
+            };
+            lines.push(format!("  x{addr:04X}:\t{word:04X}\t{word:016b}\t{text}"));
+        }
+    }
+    lines
+}
+
+/// An inclusive address range such as `x3000-x30FF`.
+pub fn parse_range(text: &str) -> Option<(u16, u16)> {
+    let (start, end) = text.split_once('-')?;
+    let parse = |addr: &str| u16::from_str_radix(addr.trim().trim_start_matches(['x', 'X']), 16).ok();
+    match (parse(start), parse(end)) {
+        (Some(start), Some(end)) if start <= end => Some((start, end)),
+        _ => None,
+    }
+}
+
+/// `segments` in address order, joining those which follow on from each other (the loader splits
+/// a segment at its entry point).
+fn coalesce(segments: &[ExecutableImageIn]) -> Vec<ExecutableImageIn> {
+    let mut sorted: Vec<&ExecutableImageIn> = segments.iter().collect();
+    sorted.sort_by_key(|segment| segment.origin);
+
+    let mut joined: Vec<ExecutableImageIn> = Vec::new();
+    for segment in sorted {
+        match joined.last_mut() {
+            Some(last) if last.origin as usize + last.data.len() == segment.origin as usize => {
+                last.data.extend(&segment.data)
+            }
+            _ => joined.push(ExecutableImageIn {
+                origin: segment.origin,
+                data: segment.data.clone(),
+            }),
+        }
+    }
+    joined
+}
+
+fn end_of(segment: &ExecutableImageIn) -> u16 {
+    segment.origin.wrapping_add((segment.data.len() as u16).wrapping_sub(1))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SOURCE: &str = ".ORIG x3000
+        LD    R1, COUNT
+        JSR   PRINT
+LOOP    ADD   R1, R1, #-1
+        BRp   LOOP
+        HALT
+PRINT   RET
+COUNT   .FILL #5
+        .END";
+
+    fn image() -> (Vec<ExecutableImageIn>, Vec<Symbol>) {
+        let mut asm = Assembler::new("prog.asm");
+        asm.load_str(SOURCE);
+        let img = asm.assemble(vec![]).unwrap();
+        let writes: Vec<MemoryWrite> = img
+            .instructions
+            .iter()
+            .chain(img.data.iter())
+            .map(|w| MemoryWrite { rel_addr: w.rel_addr, value: w.value })
+            .collect();
+        let segments = vec![
+            ExecutableImageIn::from_writes(img.origin, &writes),
+            ExecutableImageIn { origin: 0x4000, data: vec![0xF025] },
+        ];
+        (segments, img.symbol_table)
+    }
+
+    #[test]
+    pub fn headers_and_symbols() {
+        let (segments, symbols) = image();
+        assert_eq!(
+            headers("prog.obj", &segments, None),
+            vec![
+                "prog.obj:",
+                "Origin:\tx3000",
+                "Words:\t8",
+                "Segments:\t2",
+                "\tx3000 - x3006\t    7 words",
+                "\tx4000 - x4000\t    1 words"
+            ]
+        );
+        let table = super::symbols(&symbols);
+        assert_eq!(table[1], "x3002\tprivate\tcode    \t    1\tLOOP");
+        assert_eq!(table[3], "x3006\tprivate\tdata    \t    1\tCOUNT");
+    }
+
+    #[test]
+    pub fn disassembly() {
+        let (segments, symbols) = image();
+        let lines = disassemble(&segments, &symbols, None);
+        assert_eq!(lines[0], "  x3000:\t2205\t0010001000000101\tLD\tR1, [COUNT]");
+        assert_eq!(lines[1], "  x3001:\t4803\t0100100000000011\tJSR\t[PRINT]");
+        assert_eq!(lines[2], "LOOP:");
+        assert!(lines[4].ends_with("BRP\t[LOOP]"));
+        assert!(lines[5].ends_with("HALT\t"));
+        assert!(lines[7].ends_with("RET\t"));
+        assert_eq!(lines[9], "  x3006:\t0005\t0000000000000101\t.FILL\tx0005");
+        assert!(lines[10].starts_with("  x4000:"));
+
+        let lines = disassemble(&segments, &symbols, parse_range("x3003-x3004"));
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("[LOOP]"));
+
+        assert_eq!(parse_range("x3000-X30ff"), Some((0x3000, 0x30FF)));
+        assert_eq!(parse_range("x3010-x3000"), None);
+        assert_eq!(parse_range("x3000"), None);
+    }
+}
@@ -3,6 +3,7 @@ use crate::binary_utils::*;
 use core::panic;
 
 use super::assemble;
+use super::assemble::Dialect;
 //use std::thread::current;
 
 #[derive(Clone)]
@@ -66,7 +67,39 @@ impl PartialEq for Token {
     }
 }
 
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = |number: &NumberLiteral| match number.sign {
+            Sign::MINUS => "-",
+            Sign::PLUS => "",
+        };
+        match self {
+            Self::DecimalLiteral(number) => write!(f, "#{}{}", sign(number), number.value),
+            Self::HexLiteral(number) => write!(f, "x{}{:X}", sign(number), number.value),
+            Self::BinLiteral(number) => write!(f, "b{}{:b}", sign(number), number.value),
+            Self::Register(r) => write!(f, "R{r}"),
+            Self::Label(text) | Self::Instruction(text) => write!(f, "{text}"),
+            Self::Comma => write!(f, ","),
+            Self::Directive(dir) => write!(f, ".{dir}"),
+            Self::StringLiteral(text) => write!(f, "\"{text}\""),
+            Self::AlphabeticLblRegOrInstr => Ok(()),
+        }
+    }
+}
+
 impl Token {
+    /// Renders a token stream back into source text, e.g. `ADD R0, R1, #5`
+    pub fn line_to_string(tokens: &[Token]) -> String {
+        let mut text = String::new();
+        for token in tokens {
+            if !text.is_empty() && !token.is(&Token::Comma) {
+                text.push(' ');
+            }
+            text += &token.to_string();
+        }
+        text
+    }
+
     pub fn tokenize_str(line: &str) -> Vec<Token> {
         match Self::tokenize_line(&SourceLine::new(line, 0, 0)) {
             Ok(tokens) => tokens,
@@ -82,7 +115,15 @@ impl Token {
     }
 
     pub fn is_directive(name: &str) -> bool {
-        vec!["BLKW", "FILL", "ORIG", "END", "STRINGZ", "IMPORT", "EXPORT"].contains(&name)
+        [
+            "BLKW", "FILL", "ORIG", "END", "STRINGZ", "IMPORT", "EXPORT", "WEAK", "PROC", "ENDP",
+            "POOL",
+        ]
+        .contains(&name)
+    }
+
+    pub fn is_number(&self) -> bool {
+        matches!(self, Self::DecimalLiteral(_) | Self::HexLiteral(_))
     }
 
     pub fn is(&self, other: &Self) -> bool {
@@ -233,13 +274,26 @@ impl Token {
     }
 
     pub fn tokenize_line(line: &SourceLine) -> Result<Vec<Token>, String> {
+        Self::tokenize_line_in(line, Dialect::Extended)
+    }
+
+    /// Tokenizes a line written in `dialect`. In `Dialect::Compat` a `label:` loses its colon,
+    /// directives may be lower case, a `;` ends the line wherever it appears, and
+    /// `.EXTERNAL NAME` becomes `NAME .IMPORT`. Directives may be lower case in `Dialect::Strict`
+    /// too, as they may be in the LC-3 specification.
+    pub fn tokenize_line_in(line: &SourceLine, dialect: Dialect) -> Result<Vec<Token>, String> {
         let mut token_stream = Vec::new();
 
         let mut current_token: Option<Token> = None;
         let mut current_token_text = String::new();
 
+        let compat = dialect == Dialect::Compat;
+        let any_case_directives = compat || dialect == Dialect::Strict;
         let mut index = 0;
         let mut line_as_chars: String = String::from(line.text.trim());
+        if compat {
+            line_as_chars = String::from(strip_comment(&line_as_chars).trim_end());
+        }
         line_as_chars.push('\n');
 
         for c in line_as_chars.chars() {
@@ -317,7 +371,7 @@ impl Token {
                             if c == ',' || c == ' ' || c == '\n' || c == '\t' {
                                 let text = &current_token_text;
                                 //Register
-                                if text.starts_with("R")
+                                if text.starts_with(['R', 'r'])
                                     && text.len() == 2
                                     && text.chars().nth(1).unwrap().is_ascii_digit()
                                 {
@@ -344,7 +398,9 @@ impl Token {
                                     token_stream.push(Self::Instruction(text.clone()));
                                 }
                                 //Label
-                                else {
+                                else if compat && text.len() > 1 && text.ends_with(':') {
+                                    token_stream.push(Self::Label(text.trim_end_matches(':').to_string()));
+                                } else {
                                     token_stream.push(Self::Label(text.clone()));
                                 }
 
@@ -571,7 +627,12 @@ impl Token {
                         Self::Directive(_) => {
                             if !c.is_ascii_alphabetic() {
                                 if c == ',' || c == ' ' || c == '\n' || c == '\t' {
-                                    if !Token::is_directive(&current_token_text) {
+                                    if any_case_directives {
+                                        current_token_text.make_ascii_uppercase();
+                                    }
+                                    if !(Token::is_directive(&current_token_text)
+                                        || (compat && current_token_text == "EXTERNAL"))
+                                    {
                                         return Err(format!(
                                             "'.{}' is not a valid directive.",
                                             current_token_text
@@ -621,10 +682,34 @@ impl Token {
         }
         //println!("{}", current_token_text);
 
+        if let Some(Token::Directive(dir)) = token_stream.first() {
+            if dir == "EXTERNAL" {
+                return match token_stream.as_slice() {
+                    [_, Token::Label(name)] => {
+                        Ok(vec![Token::Label(name.clone()), Token::Directive(String::from("IMPORT"))])
+                    }
+                    _ => Err(String::from("Expected a single label after .EXTERNAL")),
+                };
+            }
+        }
+
         Ok(token_stream)
     }
 }
 
+/// Cuts a line at the first `;` which is not inside a string literal.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -682,6 +767,44 @@ mod test {
         expect_directive(".IMPORT", "IMPORT");
     }
 
+    #[test]
+    pub fn compat_dialect() {
+        let compat = |line: &str| Token::tokenize_line_in(&SourceLine::new(line, 0, 0), Dialect::Compat);
+
+        // Token's PartialEq only compares variants, so the names and values are matched out
+        let tokens = compat("LOOP: ADD R0, R0, #-1").unwrap();
+        let [
+            Token::Label(label),
+            Token::Instruction(instr),
+            Token::Register(0),
+            Token::Comma,
+            Token::Register(0),
+            Token::Comma,
+            Token::DecimalLiteral(number),
+        ] = tokens.as_slice()
+        else {
+            unreachable!("{tokens:?}");
+        };
+        assert_eq!((label.as_str(), instr.as_str()), ("LOOP", "ADD"));
+        assert_eq!(number, &NumberLiteral { sign: Sign::MINUS, value: 1, bits: 1 });
+
+        let tokens = compat(".orig x3000;start").unwrap();
+        let [Token::Directive(dir), Token::HexLiteral(number)] = tokens.as_slice() else {
+            unreachable!("{tokens:?}");
+        };
+        assert_eq!((dir.as_str(), number.value), ("ORIG", 0x3000));
+
+        let tokens = compat(".external PRINT").unwrap();
+        let [Token::Label(label), Token::Directive(dir)] = tokens.as_slice() else {
+            unreachable!("{tokens:?}");
+        };
+        assert_eq!((label.as_str(), dir.as_str()), ("PRINT", "IMPORT"));
+        assert!(compat(".EXTERNAL").is_err());
+
+        expect_err(".orig x3000");
+        expect_err(".EXTERNAL PRINT");
+    }
+
     #[test]
     pub fn directives_fail() {
         expect_err(".");
@@ -219,6 +219,19 @@ pub fn truncate_to_n_bit(word: u16, n: u16) -> u16 {
     result
 }
 
+/// CRC-32 (IEEE 802.3, as used by zip and PNG) of `bytes`.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
 pub mod instructions {
 
     use super::*;
@@ -320,6 +333,10 @@ pub mod test {
             0b1000
         );
     }
-}
 
-//0110
+    #[test]
+    pub fn crc() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+}
@@ -0,0 +1,179 @@
+use console::Term;
+use std::collections::VecDeque;
+use std::io::{self, IsTerminal, Read, Write};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// A character of input to the VM's keyboard, as polled by `ConsoleBackend::poll_char`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConsoleInput {
+    Char(u8),
+    /// Nothing has been typed yet.
+    Waiting,
+    /// No more input will come, e.g. at the end of a piped file.
+    Closed,
+}
+
+/// Where the VM's console (the keyboard and display registers) reads and writes characters.
+pub trait ConsoleBackend: Send {
+    /// The next character of input, without waiting for one.
+    fn poll_char(&mut self) -> ConsoleInput;
+    fn write_char(&mut self, ch: u8) -> io::Result<()>;
+}
+
+/// Reads `input` on a thread of its own, so that it can be polled without blocking.
+fn spawn_reader<R: Read + Send + 'static>(mut input: R) -> Receiver<u8> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut byte = [0u8];
+        // Ends at the end of the input, or once the receiver is gone
+        while let Ok(1) = input.read(&mut byte) {
+            if sender.send(byte[0]).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+fn poll_receiver(receiver: &Receiver<u8>) -> ConsoleInput {
+    match receiver.try_recv() {
+        Ok(ch) => ConsoleInput::Char(ch),
+        Err(TryRecvError::Empty) => ConsoleInput::Waiting,
+        Err(TryRecvError::Disconnected) => ConsoleInput::Closed,
+    }
+}
+
+/// The interactive terminal: each key is read as it is pressed, without waiting for Enter.
+pub struct TerminalConsole {
+    term: Term,
+    keys: Receiver<u8>,
+}
+
+impl TerminalConsole {
+    pub fn new() -> Self {
+        let (sender, keys) = mpsc::channel();
+        thread::spawn(move || {
+            let term = Term::stdout();
+            while let Ok(ch) = term.read_char() {
+                if !ch.is_ascii() || sender.send(ch as u8).is_err() {
+                    break;
+                }
+            }
+        });
+        Self { term: Term::stdout(), keys }
+    }
+}
+
+impl Default for TerminalConsole {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConsoleBackend for TerminalConsole {
+    fn poll_char(&mut self) -> ConsoleInput {
+        poll_receiver(&self.keys)
+    }
+
+    fn write_char(&mut self, ch: u8) -> io::Result<()> {
+        self.term.write_all(&[ch])
+    }
+}
+
+/// Byte streams, e.g. stdin and stdout when input is piped: `echo "abc" | lc3-asm-vm load prog.obj`.
+pub struct StreamConsole<W: Write + Send> {
+    input: Receiver<u8>,
+    output: W,
+}
+
+impl<W: Write + Send> StreamConsole<W> {
+    pub fn new<R: Read + Send + 'static>(input: R, output: W) -> Self {
+        Self { input: spawn_reader(input), output }
+    }
+}
+
+impl StreamConsole<io::Stdout> {
+    pub fn stdio() -> Self {
+        Self::new(io::stdin(), io::stdout())
+    }
+}
+
+impl<W: Write + Send> ConsoleBackend for StreamConsole<W> {
+    fn poll_char(&mut self) -> ConsoleInput {
+        poll_receiver(&self.input)
+    }
+
+    fn write_char(&mut self, ch: u8) -> io::Result<()> {
+        self.output.write_all(&[ch])?;
+        self.output.flush()
+    }
+}
+
+/// In-memory input and output, e.g. for tests. Clones share the same buffers, so the output can
+/// be read from a clone after the VM has taken the console.
+#[derive(Clone, Default)]
+pub struct BufferConsole {
+    input: Arc<Mutex<VecDeque<u8>>>,
+    output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl BufferConsole {
+    pub fn new(input: &str) -> Self {
+        Self {
+            input: Arc::new(Mutex::new(input.bytes().collect())),
+            output: Arc::default(),
+        }
+    }
+
+    /// Everything written to the console so far.
+    pub fn output(&self) -> String {
+        String::from_utf8_lossy(&self.output.lock().unwrap()).to_string()
+    }
+}
+
+impl ConsoleBackend for BufferConsole {
+    fn poll_char(&mut self) -> ConsoleInput {
+        match self.input.lock().unwrap().pop_front() {
+            Some(ch) => ConsoleInput::Char(ch),
+            None => ConsoleInput::Closed,
+        }
+    }
+
+    fn write_char(&mut self, ch: u8) -> io::Result<()> {
+        self.output.lock().unwrap().push(ch);
+        Ok(())
+    }
+}
+
+/// The terminal if stdin is one, else stdin and stdout as streams.
+pub fn default_console() -> Box<dyn ConsoleBackend> {
+    if io::stdin().is_terminal() {
+        Box::new(TerminalConsole::new())
+    } else {
+        Box::new(StreamConsole::stdio())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn stream_console() {
+        let mut console = StreamConsole::new(&b"ab"[..], Vec::new());
+        let mut input = Vec::new();
+        loop {
+            match console.poll_char() {
+                ConsoleInput::Char(ch) => input.push(ch),
+                ConsoleInput::Waiting => thread::yield_now(),
+                ConsoleInput::Closed => break,
+            }
+        }
+        assert_eq!(input, b"ab");
+
+        console.write_char(b'c').unwrap();
+        assert_eq!(console.output, b"c");
+    }
+}
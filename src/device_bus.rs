@@ -0,0 +1,215 @@
+use crate::binary_utils::{flag_is_set, set_flag_false, set_flag_true};
+use crate::console_backend::{ConsoleBackend, ConsoleInput};
+
+pub const KBSR: u16 = 0xFE00;
+pub const KBDR: u16 = 0xFE02;
+pub const DSR: u16 = 0xFE04;
+pub const DDR: u16 = 0xFE06;
+
+/// Bit 15 of KBSR and DSR: a key is waiting in KBDR, or the display is ready for another character.
+const READY: u16 = 15;
+
+/// Reads of KBSR without a key, once the console's input has ended and with no other I/O in
+/// between, after which the program is taken to be stuck waiting for one.
+const STUCK_POLLS: u16 = 256;
+
+/// The keyboard status and data registers.
+#[derive(Debug, Default)]
+pub struct Keyboard {
+    status: u16,
+    data: u16,
+    // No more keys will come from the console
+    closed: bool,
+}
+
+impl Keyboard {
+    pub fn is_ready(&self) -> bool {
+        flag_is_set(self.status, READY)
+    }
+
+    /// Takes the next key from the console, unless the last one has not been read yet.
+    fn tick(&mut self, console: &mut dyn ConsoleBackend) {
+        if self.is_ready() || self.closed {
+            return;
+        }
+        match console.poll_char() {
+            ConsoleInput::Char(ch) => {
+                self.data = ch as u16;
+                self.status = set_flag_true(self.status, READY);
+            }
+            ConsoleInput::Waiting => {}
+            ConsoleInput::Closed => self.closed = true,
+        }
+    }
+}
+
+/// The display status and data registers.
+#[derive(Debug)]
+pub struct Display {
+    status: u16,
+    data: u16,
+}
+
+impl Display {
+    pub fn is_ready(&self) -> bool {
+        flag_is_set(self.status, READY)
+    }
+
+    /// Writes the character in DDR to the console, if it has not been written yet.
+    fn tick(&mut self, console: &mut dyn ConsoleBackend) -> Result<(), String> {
+        if self.is_ready() {
+            return Ok(());
+        }
+        self.status = set_flag_true(self.status, READY);
+        if self.data > 127 {
+            return Err(format!(
+                "Non-ascii character '{}' in DDR.",
+                String::from_utf16_lossy(&[self.data])
+            ));
+        }
+        console
+            .write_char(self.data as u8)
+            .map_err(|e| format!("Error writing to console {e:?}"))
+    }
+}
+
+/// The memory-mapped devices of the VM, with the console they read from and write to. The CPU
+/// reads and writes their registers through `read` and `write`, and calls `tick` after each
+/// instruction to exchange characters with the console, which never blocks.
+pub struct DeviceBus {
+    pub keyboard: Keyboard,
+    pub display: Display,
+    console: Option<Box<dyn ConsoleBackend>>,
+    // Reads of KBSR without a key since the console's input ended, reset by any other I/O
+    idle_polls: u16,
+}
+
+impl std::fmt::Debug for DeviceBus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DeviceBus")
+            .field("keyboard", &self.keyboard)
+            .field("display", &self.display)
+            .field("console", &self.console.is_some())
+            .finish()
+    }
+}
+
+impl Default for DeviceBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeviceBus {
+    pub fn new() -> Self {
+        Self {
+            keyboard: Keyboard::default(),
+            display: Display { status: set_flag_true(0, READY), data: 0 },
+            console: None,
+            idle_polls: 0,
+        }
+    }
+
+    pub fn attach_console(&mut self, console: Box<dyn ConsoleBackend>) {
+        self.console = Some(console);
+    }
+
+    /// The value of the device register at `address`, or `None` if there is none.
+    pub fn read(&mut self, address: u16) -> Option<u16> {
+        let value = match address {
+            KBSR => {
+                if !self.keyboard.is_ready() && self.keyboard.closed {
+                    self.idle_polls = self.idle_polls.saturating_add(1);
+                }
+                return Some(self.keyboard.status);
+            }
+            KBDR => {
+                self.keyboard.status = set_flag_false(self.keyboard.status, READY);
+                Some(self.keyboard.data)
+            }
+            DSR => Some(self.display.status),
+            DDR => Some(self.display.data),
+            _ => return None,
+        };
+        self.idle_polls = 0;
+        value
+    }
+
+    /// Writes to the device register at `address`, returning false if there is none.
+    pub fn write(&mut self, address: u16, value: u16) -> bool {
+        self.idle_polls = 0;
+        match address {
+            KBSR => self.keyboard.status = value,
+            KBDR => self.keyboard.data = value,
+            // Writing DSR has no effect
+            DSR => {}
+            DDR => {
+                self.display.data = value;
+                self.display.status = set_flag_false(self.display.status, READY);
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn tick(&mut self) -> Result<(), String> {
+        let Some(console) = self.console.as_deref_mut() else {
+            return Ok(());
+        };
+        self.keyboard.tick(console);
+        self.display.tick(console)
+    }
+
+    /// True once the program keeps polling KBSR for a key after the console's input has ended,
+    /// with no other I/O in between, so that it would wait forever.
+    pub fn input_ended(&self) -> bool {
+        self.idle_polls >= STUCK_POLLS
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::console_backend::BufferConsole;
+
+    #[test]
+    pub fn keyboard_and_display() {
+        let console = BufferConsole::new("hi");
+        let mut bus = DeviceBus::new();
+        bus.attach_console(Box::new(console.clone()));
+
+        // Nothing is taken from the console until the bus is ticked
+        assert_eq!(bus.read(KBSR), Some(0));
+        bus.tick().unwrap();
+        assert_eq!(bus.read(KBSR), Some(0x8000));
+        bus.tick().unwrap();
+        assert_eq!(bus.read(KBDR), Some(b'h' as u16));
+        assert_eq!(bus.read(KBSR), Some(0));
+        bus.tick().unwrap();
+        assert_eq!(bus.read(KBDR), Some(b'i' as u16));
+        bus.tick().unwrap();
+        assert!(!bus.input_ended());
+
+        // Only polling KBSR over and over, with no other I/O, waits forever
+        for _ in 1..STUCK_POLLS {
+            assert_eq!(bus.read(KBSR), Some(0));
+        }
+        assert!(bus.write(DDR, b'.' as u16));
+        assert_eq!(bus.read(KBSR), Some(0));
+        assert!(!bus.input_ended());
+        for _ in 1..STUCK_POLLS {
+            assert_eq!(bus.read(KBSR), Some(0));
+        }
+        assert!(bus.input_ended());
+        bus.tick().unwrap();
+
+        assert!(bus.write(DDR, b'!' as u16));
+        assert_eq!(bus.read(DSR), Some(0));
+        bus.tick().unwrap();
+        assert_eq!(bus.read(DSR), Some(0x8000));
+        assert_eq!(console.output(), ".!");
+
+        assert_eq!(bus.read(0x3000), None);
+        assert!(!bus.write(0x3000, 1));
+    }
+}
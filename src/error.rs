@@ -5,6 +5,12 @@ pub enum FileLoadError {
     FsWriteFailed,
     InvalidBinary,
     InvalidSymbols,
+    InvalidRelocations,
+    InvalidArchive,
+    InvalidIntelHex,
+    InvalidSRecord,
+    InvalidHeader,
+    NotContiguous(u16), // A word below the origin of a single block image
 }
 
 #[derive(Debug)]
@@ -18,6 +24,7 @@ pub enum LinkErrorType {
 pub enum AsmErrorType {
     LinkError,
     SyntaxError,
+    CompatibilityError,
 }
 
 impl AsmErrorType{
@@ -25,6 +32,7 @@ impl AsmErrorType{
         match self{
             Self::LinkError => "Link Error",
             Self::SyntaxError => "Syntax Error",
+            Self::CompatibilityError => "Compatibility Error",
         }
     }
 }
@@ -51,6 +59,11 @@ impl AsmblrErr {
         self
     }
 
+    pub fn compatibility_error(&mut self) -> &mut Self{
+        self.error_type = AsmErrorType::CompatibilityError;
+        self
+    }
+
     pub fn display(file_path: &str, raw_lines: &Vec<String>, errors: &Vec<Self>) {
         for e in errors {
             // let msg = &e.msg;
@@ -8,66 +8,275 @@ use std::io::Read;
 use std::io::Write;
 use tokenizer::Token;
 
+/// Byte order of the words of a `.obj` file. The standard LC-3 format, as read and written by
+/// PennSim and lc3tools, is big-endian.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Endian {
     Big,
     Little,
 }
 
+impl Endian {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "big" | "be" => Some(Self::Big),
+            "little" | "le" => Some(Self::Little),
+            _ => None,
+        }
+    }
 
+    fn merge(&self, first: u8, second: u8) -> u16 {
+        match self {
+            // merge_bytes takes the low byte first
+            Endian::Big => binary_utils::merge_bytes(second, first),
+            Endian::Little => binary_utils::merge_bytes(first, second),
+        }
+    }
 
-pub fn read_binary_from_file(path: &str, endian: Endian) -> Result<Vec<u16>, error::FileLoadError> {
-    let file_open_result = File::open(path);
+    fn split(&self, word: u16) -> [u8; 2] {
+        match self {
+            Endian::Big => word.to_be_bytes(),
+            Endian::Little => word.to_le_bytes(),
+        }
+    }
 
-    let mut file = match file_open_result {
-        Ok(f) => f,
-        Err(e) => {
-            dbg!(e);
-            return Err(error::FileLoadError::FsOpenFailed);
+    /// Guesses the byte order of a headerless image from the origin word it would have either
+    /// way. In order of preference, the image has to fit in memory below xFFFF, should start in
+    /// user space (x3000 to xFDFF), should contain more `TRAP x20`-`x25` words (nearly every
+    /// program has a `HALT`), and should start on a 256 word boundary. Big-endian wins a tie.
+    pub fn detect(contents: &[u8]) -> Self {
+        let score = |endian: Endian| {
+            let words = words_from_bytes(contents, endian);
+            let Some((origin, data)) = words.split_first() else {
+                return (false, false, 0, false);
+            };
+            let fits = *origin as usize + data.len() <= 0x10000;
+            let user_space = (0x3000..0xFE00).contains(origin);
+            let traps = data.iter().filter(|word| (0xF020..=0xF025).contains(*word)).count();
+            (fits, user_space, traps, origin & 0xFF == 0)
+        };
+        if score(Endian::Little) > score(Endian::Big) {
+            Endian::Little
+        } else {
+            Endian::Big
         }
-    };
+    }
+}
 
-    let mut contents: Vec<u8> = Vec::new();
-    let file_read_result = file.read_to_end(&mut contents);
+/// The 16-bit words of `contents`, ignoring a trailing odd byte.
+pub fn words_from_bytes(contents: &[u8], endian: Endian) -> Vec<u16> {
+    contents
+        .chunks_exact(2)
+        .map(|bytes| endian.merge(bytes[0], bytes[1]))
+        .collect()
+}
 
-    match file_read_result {
-        Ok(_) => {}
-        Err(_) => {
-            return Err(error::FileLoadError::FsReadFailed);
+/// The words of a `.obj` file, starting with its origin.
+pub fn read_binary_from_file(path: &str, endian: Endian) -> Result<Vec<u16>, error::FileLoadError> {
+    let contents = read_bytes_from_file(path)?;
+    if contents.len() % 2 != 0 || contents.is_empty() {
+        //Valid LC-3 binary should always contain an even number of bytes, since each instruction is 16-bits (=2 bytes)
+        return Err(error::FileLoadError::InvalidBinary);
+    }
+    Ok(words_from_bytes(&contents, endian))
+}
+
+/// Reads a `.obj` file, in the byte order given, or else detected by `Endian::detect`. See
+/// `parse_object`.
+pub fn read_exectuable_img_from_file(path: &str, endian: Option<Endian>) -> Result<assemble::ExecutableImageIn, error::FileLoadError> {
+    let contents = read_bytes_from_file(path)?;
+    parse_object(&contents, endian).map(|(_, img)| img)
+}
+
+/// Parses the contents of a `.obj` file. See `parse_object`.
+pub fn image_from_bytes(contents: &[u8], endian: Endian) -> Result<assemble::ExecutableImageIn, error::FileLoadError> {
+    parse_object(contents, Some(endian)).map(|(_, img)| img)
+}
+
+/// Parses the contents of a `.obj` file, which either starts with an `ObjectHeader`, which is
+/// verified and gives the byte order, or is a headerless image in the byte order given (or else
+/// detected by `Endian::detect`).
+pub fn parse_object(
+    contents: &[u8],
+    endian: Option<Endian>,
+) -> Result<(Option<ObjectHeader>, assemble::ExecutableImageIn), error::FileLoadError> {
+    match ObjectHeader::parse(contents)? {
+        Some((header, payload)) => {
+            let img = headerless_image(payload, header.endian)?;
+            if header.entry < img.origin || (header.entry - img.origin) as usize >= img.data.len() {
+                return Err(error::FileLoadError::InvalidHeader);
+            }
+            Ok((Some(header), img))
         }
-    };
+        None => {
+            let endian = endian.unwrap_or_else(|| Endian::detect(contents));
+            Ok((None, headerless_image(contents, endian)?))
+        }
+    }
+}
 
+fn headerless_image(contents: &[u8], endian: Endian) -> Result<assemble::ExecutableImageIn, error::FileLoadError> {
     if contents.len() % 2 != 0 || contents.is_empty() {
         //Valid LC-3 binary should always contain an even number of bytes, since each instruction is 16-bits (=2 bytes)
         return Err(error::FileLoadError::InvalidBinary);
     }
 
-    let mut merged_contents: Vec<u16> = Vec::new();
+    ExecutableImageIn::from_binary(words_from_bytes(contents, endian))
+}
+// pub fn binary_to_img(bin: Vec<u16>) -> assemble::ExecutableImage{
+//     assemble::ExecutableImage{
+//         origin: bin[0],
+//         name: String::new(),
+//         instructions:
+//     }
+// }
+
+/// First bytes of a `.obj` file which starts with an `ObjectHeader`.
+pub const OBJECT_MAGIC: [u8; 4] = *b"\x7FLC3";
 
-    let mut first_halves = Vec::new();
-    let mut second_halves = Vec::new();
+/// Version of the `ObjectHeader` written by `ObjectHeader::new`.
+pub const OBJECT_HEADER_VERSION: u16 = 1;
 
-    for i in 0..contents.len() {
-        if i % 2 == 0 {
-            first_halves.push(contents[i]);
-        } else {
-            second_halves.push(contents[i]);
+const OBJECT_HEADER_LEN: usize = 32;
+
+/// Optional header of a `.obj` file, before the origin and words of a standard image. All of its
+/// fields are big-endian:
+///
+/// | Bytes | Field |
+/// |-------|-------|
+/// | 0-3   | `OBJECT_MAGIC` |
+/// | 4-5   | version |
+/// | 6-7   | length of the header in bytes (32), where the image starts |
+/// | 8-9   | entry point |
+/// | 10-11 | flags: bit 0 is set if the image is little-endian |
+/// | 12-19 | build time, in seconds since 1970 |
+/// | 20-23 | assembler version (major, minor, patch), then a zero byte |
+/// | 24-27 | length of the image in words, including its origin |
+/// | 28-31 | CRC-32 of the image |
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjectHeader {
+    pub version: u16,
+    pub entry: u16,
+    pub endian: Endian,
+    pub timestamp: u64,
+    pub assembler_version: [u8; 3],
+    pub payload_words: u32,
+    pub crc: u32,
+}
+
+impl ObjectHeader {
+    /// The header of `payload`, built now (or at `SOURCE_DATE_EPOCH`, for reproducible builds)
+    /// by this version of the assembler.
+    pub fn new(entry: u16, payload: &[u8], endian: Endian) -> Self {
+        let timestamp = match std::env::var("SOURCE_DATE_EPOCH").ok().and_then(|t| t.parse().ok()) {
+            Some(timestamp) => timestamp,
+            None => std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        };
+        let mut assembler_version = [0u8; 3];
+        for (i, part) in env!("CARGO_PKG_VERSION").split('.').take(3).enumerate() {
+            assembler_version[i] = part.parse().unwrap_or(0);
         }
+
+        Self {
+            version: OBJECT_HEADER_VERSION,
+            entry,
+            endian,
+            timestamp,
+            assembler_version,
+            payload_words: (payload.len() / 2) as u32,
+            crc: binary_utils::crc32(payload),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = OBJECT_MAGIC.to_vec();
+        bytes.extend(self.version.to_be_bytes());
+        bytes.extend((OBJECT_HEADER_LEN as u16).to_be_bytes());
+        bytes.extend(self.entry.to_be_bytes());
+        bytes.extend(if self.endian == Endian::Little { 1u16 } else { 0 }.to_be_bytes());
+        bytes.extend(self.timestamp.to_be_bytes());
+        bytes.extend(self.assembler_version);
+        bytes.push(0);
+        bytes.extend(self.payload_words.to_be_bytes());
+        bytes.extend(self.crc.to_be_bytes());
+        bytes
     }
 
-    for (f, s) in first_halves.iter().zip(second_halves.iter()) {
-        //println!("")
-        match endian {
-            Endian::Little => merged_contents.push(binary_utils::merge_bytes(*s, *f)),
-            Endian::Big => merged_contents.push(binary_utils::merge_bytes(*s, *f)),
+    /// The header at the start of `contents` and the image after it, or `None` if `contents`
+    /// does not start with a header: `OBJECT_MAGIC`, then a header whose CRC matches the image
+    /// after it. A headerless image may happen to start with the magic bytes (an origin of x7F4C
+    /// whose first word is x4333), so anything less is read as headerless. Fails if the header
+    /// is of a later version.
+    pub fn parse(contents: &[u8]) -> Result<Option<(Self, &[u8])>, error::FileLoadError> {
+        if !contents.starts_with(&OBJECT_MAGIC) || contents.len() < OBJECT_HEADER_LEN {
+            return Ok(None);
+        }
+        let u16_at = |i: usize| u16::from_be_bytes([contents[i], contents[i + 1]]);
+        let u32_at = |i: usize| u32::from_be_bytes([contents[i], contents[i + 1], contents[i + 2], contents[i + 3]]);
+
+        let header_len = u16_at(6) as usize;
+        if header_len < OBJECT_HEADER_LEN || header_len > contents.len() {
+            return Ok(None);
         }
+        let mut timestamp = [0u8; 8];
+        timestamp.copy_from_slice(&contents[12..20]);
+        let header = Self {
+            version: u16_at(4),
+            entry: u16_at(8),
+            endian: if u16_at(10) & 1 == 1 { Endian::Little } else { Endian::Big },
+            timestamp: u64::from_be_bytes(timestamp),
+            assembler_version: [contents[20], contents[21], contents[22]],
+            payload_words: u32_at(24),
+            crc: u32_at(28),
+        };
+
+        let payload = &contents[header_len..];
+        if payload.len() != header.payload_words as usize * 2 || binary_utils::crc32(payload) != header.crc {
+            return Ok(None);
+        }
+        if header.version == 0 || header.version > OBJECT_HEADER_VERSION {
+            return Err(error::FileLoadError::InvalidHeader);
+        }
+        Ok(Some((header, payload)))
     }
+}
 
-    Ok(merged_contents)
+/// Writes `img` as a `.obj` file, starting with an `ObjectHeader` if `header` is set.
+pub fn write_binary_to_file(
+    path: &str,
+    img: &assemble::ExecutableImageOut,
+    endian: Endian,
+    header: bool,
+) -> Result<usize, error::FileLoadError> {
+    let mut contents = image_to_bytes(img, endian)?;
+    if header {
+        let header = ObjectHeader::new(img.entry.unwrap_or(img.origin), &contents, endian);
+        contents.splice(0..0, header.to_bytes());
+    }
+    write_bytes_to_file(path, &contents)
 }
 
+/// The contents of a `.obj` file: the origin, then every word up to the end of the image. With
+/// `Endian::Big` this is the standard LC-3 object format, as read by PennSim and lc3tools. Fails
+/// if a word is below the origin, e.g. one which wrapped around past xFFFF, as the format can't
+/// hold it.
+pub fn image_to_bytes(img: &assemble::ExecutableImageOut, endian: Endian) -> Result<Vec<u8>, error::FileLoadError> {
+    // Instructions and data may be interleaved, so they are written out in address order, and
+    // any word which is not written (a gap) is zero
+    let image = img.sparse().contiguous(img.origin).map_err(error::FileLoadError::NotContiguous)?;
+    let contents: Vec<u16> = [img.origin].into_iter().chain(image.data).collect();
 
-pub fn read_exectuable_img_from_file(path: &str, endian: Endian) -> Result<assemble::ExecutableImageIn, error::FileLoadError> {
-    let file_open_result = File::open(path);
+    Ok(contents.iter().flat_map(|word| endian.split(*word)).collect())
+}
+
+pub fn write_symbols_to_file(
+    path: &str,
+    img: &assemble::ExecutableImageOut,
+) -> Result<usize, error::FileLoadError> {
+    let file_open_result = File::create(path);
 
     let mut file = match file_open_result {
         Ok(f) => f,
@@ -76,60 +285,100 @@ pub fn read_exectuable_img_from_file(path: &str, endian: Endian) -> Result<assem
             return Err(error::FileLoadError::FsOpenFailed);
         }
     };
+    let module = match std::path::Path::new(&img.name).file_name() {
+        Some(name) => name.to_string_lossy().replace(char::is_whitespace, "_"),
+        None => img.name.clone(),
+    };
+    let mut contents = format!(".SYM {SYMBOL_FILE_VERSION}\n.MODULE {module}\n");
+    contents += &format!(
+        ";{:19}\t{:7}\t{:8}\t{:8}\t{:8}\t{:5}\t{}\n",
+        "Symbol name", "Scope", "Kind", "Rel Addr", "Abs Addr", "Size", "Line"
+    );
+    for symbol in &img.symbol_table {
+        contents += &format!(
+            "{:20}\t{:7}\t{:8}\t#{:<7}\tx{:04x}    \t{:5}\t{}\n",
+            symbol.name,
+            symbol.status.name(),
+            symbol.kind.name(),
+            as_negative_i32(symbol.rel_addr),
+            symbol.abs_addr,
+            symbol.size_in_words,
+            symbol.src_ln_number
+        );
+    }
 
-    let mut contents: Vec<u8> = Vec::new();
-    let file_read_result = file.read_to_end(&mut contents);
+    match file.write_all(contents.as_bytes()) {
+        Ok(_) => Ok(img.symbol_table.len()),
+        Err(_) => Err(error::FileLoadError::FsWriteFailed),
+    }
+}
 
-    match file_read_result {
-        Ok(_) => {}
-        Err(_) => {
-            return Err(error::FileLoadError::FsReadFailed);
+pub fn write_relocations_to_file(
+    path: &str,
+    img: &assemble::ExecutableImageOut,
+) -> Result<usize, error::FileLoadError> {
+    let mut file = match File::create(path) {
+        Ok(f) => f,
+        Err(e) => {
+            dbg!(e);
+            return Err(error::FileLoadError::FsOpenFailed);
         }
     };
 
-    if contents.len() % 2 != 0 || contents.is_empty() {
-        //Valid LC-3 binary should always contain an even number of bytes, since each instruction is 16-bits (=2 bytes)
-        return Err(error::FileLoadError::InvalidBinary);
+    let mut contents = format!(";{:8}\t{:5}\t{}\n", "Rel Addr", "Kind", "Symbol name");
+    for relocation in &img.relocations {
+        contents += &format!(
+            "#{:04}\t\t{:5}\t{}\n",
+            relocation.rel_addr,
+            relocation.kind.name(),
+            relocation.symbol
+        );
     }
 
-    let mut merged_contents: Vec<u16> = Vec::new();
+    match file.write_all(contents.as_bytes()) {
+        Ok(_) => Ok(img.relocations.len()),
+        Err(_) => Err(error::FileLoadError::FsWriteFailed),
+    }
+}
 
-    let mut first_halves = Vec::new();
-    let mut second_halves = Vec::new();
+pub fn read_relocations_from_file(path: &str) -> Result<Vec<assemble::Relocation>, error::FileLoadError> {
+    parse_relocations(&read_lines_from_file(path)?.join("\n"))
+}
 
-    for i in 0..contents.len() {
-        if i % 2 == 0 {
-            first_halves.push(contents[i]);
-        } else {
-            second_halves.push(contents[i]);
+/// Parses the contents of a `.rel` file.
+pub fn parse_relocations(contents: &str) -> Result<Vec<assemble::Relocation>, error::FileLoadError> {
+    let mut relocations = Vec::new();
+    for line in contents.lines() {
+        if line.starts_with(";") || line.trim().is_empty() {
+            continue;
         }
-    }
 
-    for (f, s) in first_halves.iter().zip(second_halves.iter()) {
-        //println!("")
-        match endian {
-            Endian::Little => merged_contents.push(binary_utils::merge_bytes(*s, *f)),
-            Endian::Big => merged_contents.push(binary_utils::merge_bytes(*s, *f)),
-        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [rel_addr, kind, symbol] = fields.as_slice() else {
+            return Err(error::FileLoadError::InvalidRelocations);
+        };
+        let rel_addr = match rel_addr.strip_prefix('#').map(|addr| addr.parse::<u16>()) {
+            Some(Ok(addr)) => addr,
+            _ => return Err(error::FileLoadError::InvalidRelocations),
+        };
+        let kind = match RelocationKind::from_name(kind) {
+            Some(kind) => kind,
+            None => return Err(error::FileLoadError::InvalidRelocations),
+        };
+        relocations.push(Relocation {
+            rel_addr,
+            kind,
+            symbol: symbol.to_string(),
+        });
     }
-
-    ExecutableImageIn::from_binary(merged_contents)
+    Ok(relocations)
 }
-// pub fn binary_to_img(bin: Vec<u16>) -> assemble::ExecutableImage{
-//     assemble::ExecutableImage{
-//         origin: bin[0],
-//         name: String::new(),
-//         instructions:
-//     }
-// }
 
-pub fn write_binary_to_file(
+pub fn write_listing_to_file(
     path: &str,
     img: &assemble::ExecutableImageOut,
 ) -> Result<usize, error::FileLoadError> {
-    let file_open_result = File::create(path);
-
-    let mut file = match file_open_result {
+    let mut file = match File::create(path) {
         Ok(f) => f,
         Err(e) => {
             dbg!(e);
@@ -137,68 +386,95 @@ pub fn write_binary_to_file(
         }
     };
 
-    let mut contents: Vec<u16> = Vec::new();
-    contents.push(img.origin);
-    contents = [
-        contents,
-        img.instructions.iter().map(|write| write.value).collect(),
-    ]
-    .concat();
-    contents = [contents, img.data.iter().map(|write| write.value).collect()].concat();
+    let mut contents = format!(";{:6}\t{:4}\t{:5}\t{}\n", "Addr", "Word", "Line", "Source");
+    for line in &img.listing {
+        // Lines marked '+' were generated by the assembler rather than written in the source
+        let marker = if line.generated { '+' } else { ' ' };
+        for (i, word) in line.words.iter().enumerate() {
+            let addr = img.origin.wrapping_add(line.rel_addr).wrapping_add(i as u16);
+            if i == 0 {
+                contents += &format!(
+                    "x{:04X}\t{:04X}\t{:4}{}\t{}\n",
+                    addr, word, line.src_ln_number, marker, line.text
+                );
+            } else {
+                contents += &format!("x{:04X}\t{:04X}\n", addr, word);
+            }
+        }
+    }
 
-    let contents: Vec<[u8; 2]> = contents.iter().map(|word| word.to_le_bytes()).collect();
-    let mut size = 0;
-    for c in &contents {
-        size += file.write(&[c[1]]).expect("Error writing bytes to file");
-        size += file.write(&[c[0]]).expect("Error writing bytes to file");
+    match file.write_all(contents.as_bytes()) {
+        Ok(_) => Ok(img.listing.len()),
+        Err(_) => Err(error::FileLoadError::FsWriteFailed),
     }
-    file.write(&[0u8, 0u8])
-        .expect("Error writing bytes to file");
-    Ok(size)
 }
 
-pub fn write_symbols_to_file(
-    path: &str,
-    img: &assemble::ExecutableImageOut,
-) -> Result<usize, error::FileLoadError> {
-    let file_open_result = File::create(path);
+pub fn read_bytes_from_file(path: &str) -> Result<Vec<u8>, error::FileLoadError> {
+    let mut file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) => {
+            dbg!(e);
+            return Err(error::FileLoadError::FsOpenFailed);
+        }
+    };
 
-    let mut file = match file_open_result {
+    let mut contents = Vec::new();
+    match file.read_to_end(&mut contents) {
+        Ok(_) => Ok(contents),
+        Err(_) => Err(error::FileLoadError::FsReadFailed),
+    }
+}
+
+pub fn write_bytes_to_file(path: &str, contents: &[u8]) -> Result<usize, error::FileLoadError> {
+    let mut file = match File::create(path) {
         Ok(f) => f,
         Err(e) => {
             dbg!(e);
             return Err(error::FileLoadError::FsOpenFailed);
         }
     };
-    let mut symbol_count = 0;
-    //file.write(format!(";{:20}\t\t{:4}\t\t{}\n", "Name","Addr","Status").as_bytes());
-    match file.write(format!(";{:20}\t\t{}\t{}\t{}\n", "Symbol name", "Rel Addr", "Abs Addr", "Scope").as_bytes()){
-        Ok(_) => (),
-        Err(_) => return Err(error::FileLoadError::FsWriteFailed)
+
+    match file.write_all(contents) {
+        Ok(_) => Ok(contents.len()),
+        Err(_) => Err(error::FileLoadError::FsWriteFailed),
     }
+}
 
-    //file.write(".SYM".as_bytes()).expect("Error writing header.");
-    
-    for symbol in &img.symbol_table {
-        file.write(
-            format!(
-                "{:20}\t\t#{:04}\t\tx{:04x}\t\t{}\n",
-                symbol.name,
-                as_negative_i32(symbol.rel_addr),
-                symbol.abs_addr,
-                symbol.status.clone() as u16
-            )
-            .as_bytes(),
-        )
-        .expect("Failure writing symbol table to file.");
-        symbol_count += 1;
-    }
-    Ok(symbol_count)
+pub fn read_lines_from_file(path: &str) -> Result<Vec<String>, error::FileLoadError> {
+    let mut file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) => {
+            dbg!(e);
+            return Err(error::FileLoadError::FsOpenFailed);
+        }
+    };
+
+    let mut contents = String::new();
+    match file.read_to_string(&mut contents) {
+        Ok(_) => Ok(contents.lines().map(|l| l.to_string()).collect()),
+        Err(_) => Err(error::FileLoadError::FsReadFailed),
+    }
+}
+
+pub fn write_lines_to_file(path: &str, lines: &[String]) -> Result<usize, error::FileLoadError> {
+    let mut file = match File::create(path) {
+        Ok(f) => f,
+        Err(e) => {
+            dbg!(e);
+            return Err(error::FileLoadError::FsOpenFailed);
+        }
+    };
+
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    match file.write_all(contents.as_bytes()) {
+        Ok(_) => Ok(lines.len()),
+        Err(_) => Err(error::FileLoadError::FsWriteFailed),
+    }
 }
 
 pub fn read_symbols_from_file(path: &str) -> Result<Vec<assemble::Symbol>, error::FileLoadError> {
     let file_open_result = File::open(path);
-    let mut symbols = Vec::new();
 
     let mut file = match file_open_result {
         Ok(f) => f,
@@ -218,6 +494,159 @@ pub fn read_symbols_from_file(path: &str) -> Result<Vec<assemble::Symbol>, error
         }
     };
 
+    parse_symbols(&contents)
+}
+
+/// Version of the `.sym` format written by `write_symbols_to_file`. Files without a `.SYM`
+/// header are read as the legacy format (version 1).
+pub const SYMBOL_FILE_VERSION: u16 = 2;
+
+/// The contents of a `.sym` file.
+pub struct SymbolFile {
+    pub version: u16,
+    pub module: String,
+    pub symbols: Vec<assemble::Symbol>,
+}
+
+/// Parses the contents of a `.sym` file.
+pub fn parse_symbols(contents: &str) -> Result<Vec<assemble::Symbol>, error::FileLoadError> {
+    parse_symbol_file(contents).map(|file| file.symbols)
+}
+
+/// Parses a `.sym` file of either format. The fields of a version 2 file are separated by tabs,
+/// which a label cannot contain, and padded with spaces.
+///
+/// ```text
+/// .SYM 2
+/// .MODULE prog.asm
+/// ;Symbol name   Scope    Kind    Rel Addr   Abs Addr   Size   Line
+/// LOOP           private  code    #2         x3002      1      7
+/// ```
+pub fn parse_symbol_file(contents: &str) -> Result<SymbolFile, error::FileLoadError> {
+    let mut lines = contents
+        .lines()
+        .filter(|line| !line.starts_with(";") && !line.trim().is_empty())
+        .peekable();
+
+    let version = match lines.peek().map(|line| line.split_whitespace().collect::<Vec<_>>()) {
+        Some(header) if header.first() == Some(&".SYM") => match header.get(1).map(|v| v.parse::<u16>()) {
+            Some(Ok(version)) if version == SYMBOL_FILE_VERSION => version,
+            _ => return Err(error::FileLoadError::InvalidSymbols),
+        },
+        _ => {
+            return Ok(SymbolFile {
+                version: 1,
+                module: String::new(),
+                symbols: parse_legacy_symbols(contents)?,
+            })
+        }
+    };
+    lines.next();
+
+    let module = match lines.next().and_then(|line| line.strip_prefix(".MODULE")) {
+        Some(module) => module.trim().to_string(),
+        None => return Err(error::FileLoadError::InvalidSymbols),
+    };
+
+    let mut symbols = Vec::new();
+    for line in lines {
+        let fields: Vec<&str> = line.split('\t').map(str::trim).collect();
+        let [name, status, kind, rel_addr, abs_addr, size, src_ln_number] = fields.as_slice() else {
+            return Err(error::FileLoadError::InvalidSymbols);
+        };
+        let number = |text: Option<&str>, radix| match text.map(|t| i32::from_str_radix(t, radix)) {
+            Some(Ok(value)) => Ok(value as u16),
+            _ => Err(error::FileLoadError::InvalidSymbols),
+        };
+        symbols.push(Symbol {
+            name: name.to_string(),
+            rel_addr: number(rel_addr.strip_prefix('#'), 10)?,
+            abs_addr: number(abs_addr.strip_prefix('x'), 16)?,
+            src_ln_number: number(Some(src_ln_number), 10)?,
+            size_in_words: number(Some(size), 10)?,
+            status: SymbolStatus::from_name(status).ok_or(error::FileLoadError::InvalidSymbols)?,
+            kind: SymbolKind::from_name(kind).ok_or(error::FileLoadError::InvalidSymbols)?,
+        });
+    }
+
+    Ok(SymbolFile {
+        version,
+        module,
+        symbols,
+    })
+}
+
+/// First line of a symbol table written by PennSim or lc3tools.
+pub const PENNSIM_SYMBOLS_HEADER: &str = "// Symbol table";
+
+/// A symbol table in the text format of PennSim and lc3tools (`lc3as`), which is what they
+/// write beside `prog.obj` as `prog.sym`:
+///
+/// ```text
+/// // Symbol table
+/// // Scope level 0:
+/// //    Symbol Name       Page Address
+/// //    ----------------  ------------
+/// //    LOOP              3002
+/// ```
+///
+/// The gap after each `//` is a tab. The format only has names and addresses, so imports are
+/// left out.
+pub fn to_pennsim_symbols(symbols: &[assemble::Symbol]) -> String {
+    let mut contents = format!("{PENNSIM_SYMBOLS_HEADER}\n// Scope level 0:\n");
+    contents += "//\tSymbol Name       Page Address\n";
+    contents += "//\t----------------  ------------\n";
+    for symbol in symbols {
+        if !matches!(symbol.status, SymbolStatus::Import) {
+            contents += &format!("//\t{:16}  {:04X}\n", symbol.name, symbol.abs_addr);
+        }
+    }
+    contents.push('\n');
+    contents
+}
+
+/// Parses a PennSim or lc3tools symbol table (see `to_pennsim_symbols`) of an image which
+/// starts at `origin`. The format has no scope, kind, size or line, so every symbol is
+/// private code.
+pub fn parse_pennsim_symbols(contents: &str, origin: u16) -> Result<Vec<assemble::Symbol>, error::FileLoadError> {
+    let mut lines = contents.lines();
+    if lines.next().map(str::trim_end) != Some(PENNSIM_SYMBOLS_HEADER) {
+        return Err(error::FileLoadError::InvalidSymbols);
+    }
+
+    let mut symbols = Vec::new();
+    for line in lines {
+        let fields: Vec<&str> = match line.trim().strip_prefix("//") {
+            Some(line) => line.split_whitespace().collect(),
+            None if line.trim().is_empty() => continue,
+            None => return Err(error::FileLoadError::InvalidSymbols),
+        };
+        // Skip the scope and column headers
+        let (name, addr) = match fields.as_slice() {
+            [name, addr] if !name.starts_with('-') && *name != "Scope" => (name, addr),
+            _ => continue,
+        };
+        let abs_addr = match u16::from_str_radix(addr.trim_start_matches(['x', 'X']), 16) {
+            Ok(addr) => addr,
+            Err(_) => return Err(error::FileLoadError::InvalidSymbols),
+        };
+        symbols.push(Symbol {
+            name: name.to_string(),
+            rel_addr: abs_addr.wrapping_sub(origin),
+            abs_addr,
+            src_ln_number: 0,
+            size_in_words: 0,
+            status: SymbolStatus::Private,
+            kind: SymbolKind::Code,
+        });
+    }
+    Ok(symbols)
+}
+
+/// Parses a `.sym` file written before the format was versioned: name, relative address,
+/// absolute address and scope (0, 1 or 2 for private, export or import) on each line.
+pub fn parse_legacy_symbols(contents: &str) -> Result<Vec<assemble::Symbol>, error::FileLoadError> {
+    let mut symbols = Vec::new();
     let contents = contents.lines();
     for line in contents.into_iter() {
         //println!("{line}");
@@ -278,8 +707,601 @@ pub fn read_symbols_from_file(path: &str) -> Result<Vec<assemble::Symbol>, error
             src_ln_number: 0,
             size_in_words: 0,
             status,
+            kind: SymbolKind::Code,
         })
     }
 
     Ok(symbols)
 }
+
+/// How an executable image is stored on disk.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    /// `.obj`: the origin, then every word up to the end of the image.
+    Binary,
+    /// Intel HEX (`.hex`, `.ihex`).
+    IntelHex,
+    /// Motorola S-records (`.srec`, `.s19`, `.s28`, `.mot`).
+    SRecord,
+}
+
+impl ImageFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "obj" | "binary" => Some(Self::Binary),
+            "ihex" | "hex" => Some(Self::IntelHex),
+            "srec" => Some(Self::SRecord),
+            _ => None,
+        }
+    }
+
+    /// The format given by the extension of `path`, if it has a known one.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = std::path::Path::new(path).extension()?.to_string_lossy().to_ascii_lowercase();
+        match extension.as_str() {
+            "obj" => Some(Self::Binary),
+            "hex" | "ihex" => Some(Self::IntelHex),
+            "srec" | "s19" | "s28" | "mot" => Some(Self::SRecord),
+            _ => None,
+        }
+    }
+
+    /// The format of a file, by its extension or else by its contents. A binary image can start
+    /// with the byte of ':' or 'S', so the whole first line has to look like a record.
+    pub fn detect(path: &str, contents: &[u8]) -> Self {
+        if let Some(format) = Self::from_path(path) {
+            return format;
+        }
+        let first_line = contents.split(|b| *b == b'\n').next().unwrap_or_default();
+        let first_line = first_line.strip_suffix(b"\r").unwrap_or(first_line);
+        let is_hex = |bytes: &[u8]| !bytes.is_empty() && bytes.iter().all(u8::is_ascii_hexdigit);
+        match first_line {
+            [b':', rest @ ..] if is_hex(rest) => Self::IntelHex,
+            [b'S', kind, rest @ ..] if kind.is_ascii_digit() && is_hex(rest) => Self::SRecord,
+            _ => Self::Binary,
+        }
+    }
+
+    /// The extension written for this format.
+    pub fn extension(&self) -> &str {
+        match self {
+            Self::Binary => "obj",
+            Self::IntelHex => "hex",
+            Self::SRecord => "srec",
+        }
+    }
+}
+
+/// The contiguous runs of words written by `img`, at their absolute addresses. Any gap, such as
+/// between two linked modules, starts a new segment.
+pub fn image_segments(img: &assemble::ExecutableImageOut) -> Vec<assemble::ExecutableImageIn> {
+    img.sparse().segments()
+}
+
+/// Writes `img` as `format`, returning the number of bytes written. `endian` and `header` only
+/// apply to `.obj` files; HEX and S-records are always big-endian, and hold the entry point.
+pub fn write_image_to_file(
+    path: &str,
+    img: &assemble::ExecutableImageOut,
+    format: ImageFormat,
+    endian: Endian,
+    header: bool,
+) -> Result<usize, error::FileLoadError> {
+    let segments = image_segments(img);
+    let entry = img.entry.unwrap_or(img.origin);
+    match format {
+        ImageFormat::Binary => write_binary_to_file(path, img, endian, header),
+        ImageFormat::IntelHex => write_bytes_to_file(path, to_intel_hex(&segments, entry).as_bytes()),
+        ImageFormat::SRecord => {
+            let header = std::path::Path::new(&img.name).file_name().map(|n| n.to_string_lossy().to_string());
+            write_bytes_to_file(path, to_srecords(&segments, entry, &header.unwrap_or_default()).as_bytes())
+        }
+    }
+}
+
+/// Reads an image in any `ImageFormat`, detected by `ImageFormat::detect`. A `.obj` file is a
+/// single segment, read by `parse_object`; a HEX or S-record file may hold several. The segment
+/// which starts at the entry point comes first.
+pub fn read_image_segments_from_file(
+    path: &str,
+    endian: Option<Endian>,
+) -> Result<Vec<assemble::ExecutableImageIn>, error::FileLoadError> {
+    let contents = read_bytes_from_file(path)?;
+    match ImageFormat::detect(path, &contents) {
+        ImageFormat::Binary => {
+            let (header, img) = parse_object(&contents, endian)?;
+            Ok(entry_first(vec![img], header.map(|header| header.entry)))
+        }
+        ImageFormat::IntelHex => match std::str::from_utf8(&contents) {
+            Ok(text) => parse_intel_hex(text),
+            Err(_) => Err(error::FileLoadError::InvalidIntelHex),
+        },
+        ImageFormat::SRecord => match std::str::from_utf8(&contents) {
+            Ok(text) => parse_srecords(text),
+            Err(_) => Err(error::FileLoadError::InvalidSRecord),
+        },
+    }
+}
+
+/// Words per data record of the HEX and S-record writers.
+const RECORD_WORDS: usize = 8;
+
+/// Intel HEX of `segments`, byte addressed with each word big-endian (the byte order of `.obj`),
+/// so word `x3000` is at byte address `x6000`. Addresses above `xFFFF` use an extended linear
+/// address record, and the entry point is written as a start linear address record.
+pub fn to_intel_hex(segments: &[assemble::ExecutableImageIn], entry: u16) -> String {
+    fn record(kind: u8, addr: u16, data: &[u8]) -> String {
+        let mut bytes = vec![data.len() as u8];
+        bytes.extend(addr.to_be_bytes());
+        bytes.push(kind);
+        bytes.extend(data);
+        let checksum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)).wrapping_neg();
+        bytes.push(checksum);
+        format!(":{}\n", hex_string(&bytes))
+    }
+
+    let mut hex = String::new();
+    let mut upper = 0u16;
+    for segment in segments {
+        for (i, chunk) in segment.data.chunks(RECORD_WORDS).enumerate() {
+            let byte_addr = (segment.origin as u32 + (i * RECORD_WORDS) as u32) * 2;
+            if (byte_addr >> 16) as u16 != upper {
+                upper = (byte_addr >> 16) as u16;
+                hex.push_str(&record(0x04, 0, &upper.to_be_bytes()));
+            }
+            let data: Vec<u8> = chunk.iter().flat_map(|word| word.to_be_bytes()).collect();
+            hex.push_str(&record(0x00, byte_addr as u16, &data));
+        }
+    }
+    hex.push_str(&record(0x05, 0, &(entry as u32 * 2).to_be_bytes()));
+    hex.push_str(&record(0x01, 0, &[]));
+    hex
+}
+
+/// Parses Intel HEX written by `to_intel_hex` or other tools, with extended segment and linear
+/// addresses.
+pub fn parse_intel_hex(contents: &str) -> Result<Vec<assemble::ExecutableImageIn>, error::FileLoadError> {
+    let invalid = error::FileLoadError::InvalidIntelHex;
+    let mut bytes = std::collections::BTreeMap::new();
+    let mut base = 0u32;
+    let mut entry = None;
+    let mut ended = false;
+
+    for line in contents.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        if ended {
+            return Err(invalid);
+        }
+        let record = match line.strip_prefix(':').and_then(parse_hex_bytes) {
+            Some(record) if record.len() >= 5 && record.len() == record[0] as usize + 5 => record,
+            _ => return Err(invalid),
+        };
+        if record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(invalid);
+        }
+
+        let addr = u16::from_be_bytes([record[1], record[2]]) as u32;
+        let data = &record[4..record.len() - 1];
+        match (record[3], data) {
+            (0x00, _) => {
+                for (i, byte) in data.iter().enumerate() {
+                    bytes.insert(base + addr + i as u32, *byte);
+                }
+            }
+            (0x01, []) => ended = true,
+            (0x02, [hi, lo]) => base = (u16::from_be_bytes([*hi, *lo]) as u32) << 4,
+            (0x03, [cs_hi, cs_lo, ip_hi, ip_lo]) => {
+                entry = Some(((u16::from_be_bytes([*cs_hi, *cs_lo]) as u32) << 4) + u16::from_be_bytes([*ip_hi, *ip_lo]) as u32)
+            }
+            (0x04, [hi, lo]) => base = (u16::from_be_bytes([*hi, *lo]) as u32) << 16,
+            (0x05, [a, b, c, d]) => entry = Some(u32::from_be_bytes([*a, *b, *c, *d])),
+            _ => return Err(invalid),
+        }
+    }
+
+    if !ended {
+        return Err(invalid);
+    }
+    segments_from_bytes(&bytes, entry).ok_or(invalid)
+}
+
+/// S-records of `segments`, addressed like `to_intel_hex`. `S1` records are used if every byte
+/// address fits in 16 bits, otherwise `S2`; `header` goes in the `S0` record.
+pub fn to_srecords(segments: &[assemble::ExecutableImageIn], entry: u16, header: &str) -> String {
+    fn record(kind: u8, addr: u32, addr_len: usize, data: &[u8]) -> String {
+        let mut bytes = vec![(addr_len + data.len() + 1) as u8];
+        bytes.extend(&addr.to_be_bytes()[4 - addr_len..]);
+        bytes.extend(data);
+        let checksum = !bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        bytes.push(checksum);
+        format!("S{kind}{}\n", hex_string(&bytes))
+    }
+
+    let end = segments
+        .iter()
+        .map(|segment| (segment.origin as u32 + segment.data.len() as u32) * 2)
+        .max()
+        .unwrap_or(0);
+    let (data_kind, end_kind, addr_len) = if end <= 0x10000 { (1, 9, 2) } else { (2, 8, 3) };
+
+    let mut srec = record(0, 0, 2, header.as_bytes());
+    let mut count = 0;
+    for segment in segments {
+        for (i, chunk) in segment.data.chunks(RECORD_WORDS).enumerate() {
+            let byte_addr = (segment.origin as u32 + (i * RECORD_WORDS) as u32) * 2;
+            let data: Vec<u8> = chunk.iter().flat_map(|word| word.to_be_bytes()).collect();
+            srec.push_str(&record(data_kind, byte_addr, addr_len, &data));
+            count += 1;
+        }
+    }
+    if count <= 0xFFFF {
+        srec.push_str(&record(5, count, 2, &[]));
+    }
+    srec.push_str(&record(end_kind, entry as u32 * 2, addr_len, &[]));
+    srec
+}
+
+/// Parses S-records with 16, 24 or 32-bit addresses.
+pub fn parse_srecords(contents: &str) -> Result<Vec<assemble::ExecutableImageIn>, error::FileLoadError> {
+    let invalid = error::FileLoadError::InvalidSRecord;
+    let mut bytes = std::collections::BTreeMap::new();
+    let mut entry = None;
+    let mut data_records = 0u32;
+
+    for line in contents.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let (kind, record) = match line.strip_prefix('S').and_then(|rest| rest.split_at_checked(1)) {
+            Some((kind, rest)) => match (kind.parse::<u8>(), parse_hex_bytes(rest)) {
+                (Ok(kind), Some(record)) if !record.is_empty() && record.len() == record[0] as usize + 1 => (kind, record),
+                _ => return Err(invalid),
+            },
+            None => return Err(invalid),
+        };
+        if record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0xFF {
+            return Err(invalid);
+        }
+
+        let addr_len = match kind {
+            0 | 1 | 5 | 9 => 2,
+            2 | 6 | 8 => 3,
+            3 | 7 => 4,
+            _ => return Err(invalid),
+        };
+        if record.len() < addr_len + 2 {
+            return Err(invalid);
+        }
+        let addr = record[1..1 + addr_len].iter().fold(0u32, |addr, b| addr << 8 | *b as u32);
+        let data = &record[1 + addr_len..record.len() - 1];
+        match kind {
+            1..=3 => {
+                for (i, byte) in data.iter().enumerate() {
+                    bytes.insert(addr + i as u32, *byte);
+                }
+                data_records += 1;
+            }
+            5 | 6 if addr != data_records => return Err(invalid),
+            7..=9 => entry = Some(addr),
+            _ => {}
+        }
+    }
+
+    segments_from_bytes(&bytes, entry).ok_or(invalid)
+}
+
+/// Joins big-endian byte pairs into runs of words, starting a segment at every gap. Returns
+/// `None` if a word is only half written or lies outside of memory.
+fn segments_from_bytes(
+    bytes: &std::collections::BTreeMap<u32, u8>,
+    entry: Option<u32>,
+) -> Option<Vec<assemble::ExecutableImageIn>> {
+    let mut segments: Vec<ExecutableImageIn> = Vec::new();
+    let mut iter = bytes.iter();
+    while let Some((addr, hi)) = iter.next() {
+        let (next, lo) = iter.next()?;
+        if addr % 2 != 0 || *next != addr + 1 || addr / 2 > 0xFFFF {
+            return None;
+        }
+        let word_addr = (addr / 2) as u16;
+        let word = u16::from_be_bytes([*hi, *lo]);
+        match segments.last_mut() {
+            Some(segment) if segment.origin as u32 + segment.data.len() as u32 == word_addr as u32 => {
+                segment.data.push(word)
+            }
+            _ => segments.push(ExecutableImageIn {
+                origin: word_addr,
+                data: vec![word],
+            }),
+        }
+    }
+    if segments.is_empty() {
+        return None;
+    }
+    Some(entry_first(segments, entry.map(|entry| (entry / 2) as u16)))
+}
+
+/// The VM starts at the origin of the first image, so the segment with the entry point goes
+/// first, split at the entry point if it is not where the segment starts.
+pub fn entry_first(mut segments: Vec<assemble::ExecutableImageIn>, entry: Option<u16>) -> Vec<assemble::ExecutableImageIn> {
+    if let Some(entry) = entry {
+        if let Some(i) = segments
+            .iter()
+            .position(|s| entry >= s.origin && ((entry - s.origin) as usize) < s.data.len())
+        {
+            let mut segment = segments.remove(i);
+            if entry != segment.origin {
+                let tail = segment.data.split_off((entry - segment.origin) as usize);
+                segments.insert(i, segment);
+                segment = ExecutableImageIn { origin: entry, data: tail };
+            }
+            segments.insert(0, segment);
+        }
+    }
+    segments
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02X}")).collect()
+}
+
+fn parse_hex_bytes(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn symbol_file_round_trip() {
+        let mut asm = Assembler::new("dir/prog.asm");
+        asm.load_str(
+            ".ORIG x3000
+            MAIN .EXPORT LD R0, N
+                 HALT
+            N    .FILL #5
+            $msg .STRINGZ \"hi\"
+            .END",
+        );
+        let img = asm.assemble(vec![]).unwrap();
+
+        let path = std::env::temp_dir().join(format!("lc3-sym-test-{}.sym", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        write_symbols_to_file(&path, &img).unwrap();
+        let file = parse_symbol_file(&read_lines_from_file(&path).unwrap().join("\n")).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((file.version, file.module.as_str()), (2, "prog.asm"));
+        let symbols: Vec<(&str, &str, SymbolKind, u16, u16, u16)> = file
+            .symbols
+            .iter()
+            .map(|s| (s.name.as_str(), s.status.name(), s.kind, s.abs_addr, s.size_in_words, s.src_ln_number))
+            .collect();
+        assert_eq!(
+            symbols,
+            vec![
+                ("MAIN", "export", SymbolKind::Code, 0x3000, 1, 2),
+                ("N", "private", SymbolKind::Data, 0x3002, 1, 4),
+                ("$msg", "private", SymbolKind::Data, 0x3003, 3, 5),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn symbol_file_keeps_unusual_names() {
+        let mut img = ExecutableImageOut::new(String::from("prog.obj"));
+        for (i, name) in ["A;B", "two words", "#1", "x\"y\""].into_iter().enumerate() {
+            img.symbol_table.push(Symbol {
+                name: name.to_string(),
+                rel_addr: i as u16,
+                abs_addr: 0x3000 + i as u16,
+                src_ln_number: i as u16 + 1,
+                size_in_words: 1,
+                status: SymbolStatus::Private,
+                kind: SymbolKind::Code,
+            });
+        }
+
+        let path = std::env::temp_dir().join(format!("lc3-sym-names-test-{}.sym", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        write_symbols_to_file(&path, &img).unwrap();
+        let symbols = read_symbols_from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let names: Vec<(&str, u16)> = symbols.iter().map(|s| (s.name.as_str(), s.abs_addr)).collect();
+        assert_eq!(names, vec![("A;B", 0x3000), ("two words", 0x3001), ("#1", 0x3002), ("x\"y\"", 0x3003)]);
+    }
+
+    #[test]
+    pub fn legacy_symbol_file() {
+        let contents = ";Symbol name\t\tRel Addr\tAbs Addr\tScope\nPRINT\t\t#0010\t\tx3010\t\t1\nREAD\t\t#0000\t\tx4000\t\t2\n";
+        let file = parse_symbol_file(contents).unwrap();
+        assert_eq!(file.version, 1);
+        assert_eq!(file.symbols.len(), 2);
+        assert!(matches!(file.symbols[1].status, SymbolStatus::Import));
+        assert_eq!(file.symbols[0].abs_addr, 0x3010);
+
+        assert!(parse_symbol_file(".SYM 9\n.MODULE a\n").is_err());
+        assert!(parse_symbol_file(".SYM 2\n.MODULE a\nF export code #0 x3000 1\n").is_err());
+        assert!(parse_symbol_file(".SYM 2\n.MODULE a\nF public code #0 x3000 1 1\n").is_err());
+    }
+
+    fn segments() -> Vec<ExecutableImageIn> {
+        vec![
+            ExecutableImageIn { origin: 0x3000, data: (0..10).collect() },
+            ExecutableImageIn { origin: 0x3100, data: vec![0xC1C0] },
+            ExecutableImageIn { origin: 0xFFFE, data: vec![0x1234, 0xABCD] },
+        ]
+    }
+
+    fn words(segments: &[ExecutableImageIn]) -> Vec<(u16, Vec<u16>)> {
+        segments.iter().map(|s| (s.origin, s.data.clone())).collect()
+    }
+
+    #[test]
+    pub fn intel_hex_round_trip() {
+        let hex = to_intel_hex(&[ExecutableImageIn { origin: 0x3000, data: vec![0xF025] }], 0x3000);
+        assert_eq!(hex, ":02600000F02589\n:040000050000600097\n:00000001FF\n");
+
+        let hex = to_intel_hex(&segments(), 0x3100);
+        assert!(hex.contains(":020000040001F9\n"));
+        let read = parse_intel_hex(&hex).unwrap();
+        let mut expected = words(&segments());
+        let entry = expected.remove(1);
+        expected.insert(0, entry);
+        assert_eq!(words(&read), expected);
+
+        // Entry point in the middle of a segment
+        let read = parse_intel_hex(&to_intel_hex(&segments(), 0x3004)).unwrap();
+        assert_eq!((read[0].origin, read[0].data.len()), (0x3004, 6));
+        assert_eq!((read[1].origin, read[1].data.len()), (0x3000, 4));
+    }
+
+    #[test]
+    pub fn srecord_round_trip() {
+        let srec = to_srecords(&[ExecutableImageIn { origin: 0x3000, data: vec![0xF025] }], 0x3000, "a");
+        assert_eq!(srec, "S0040000619A\nS1056000F02585\nS5030001FB\nS90360009C\n");
+
+        let srec = to_srecords(&segments(), 0x3000, "prog.asm");
+        assert!(srec.lines().nth(1).unwrap().starts_with("S2"));
+        assert!(srec.trim_end().lines().last().unwrap().starts_with("S8"));
+        assert_eq!(words(&parse_srecords(&srec).unwrap()), words(&segments()));
+    }
+
+    #[test]
+    pub fn invalid_records() {
+        let hex = to_intel_hex(&segments(), 0x3000);
+        // Bad checksum, missing end of file, half a word
+        assert!(parse_intel_hex(&hex.replacen(":10600000", ":10600001", 1)).is_err());
+        assert!(parse_intel_hex(&hex.replace(":00000001FF\n", "")).is_err());
+        assert!(parse_intel_hex(":01600000128D\n:00000001FF\n").is_err());
+
+        let srec = to_srecords(&segments(), 0x3000, "");
+        assert!(parse_srecords(&srec.replacen("S2", "S1", 1)).is_err());
+        assert!(parse_srecords("S5030005F7\n").is_err());
+    }
+
+    #[test]
+    pub fn detects_image_format() {
+        assert_eq!(ImageFormat::detect("prog.HEX", b"\x30\x00"), ImageFormat::IntelHex);
+        assert_eq!(ImageFormat::detect("prog.s19", b""), ImageFormat::SRecord);
+        assert_eq!(ImageFormat::detect("prog", b":02600000F02589\r\n"), ImageFormat::IntelHex);
+        assert_eq!(ImageFormat::detect("prog", b"S1056000F02585\n"), ImageFormat::SRecord);
+        // A binary image starting at x3A00 begins with ':'
+        assert_eq!(ImageFormat::detect("prog", b"\x3A\x00\xF0\x25"), ImageFormat::Binary);
+        assert_eq!(ImageFormat::detect("prog.bin", b"\x30\x00"), ImageFormat::Binary);
+    }
+
+    const COUNT_ASM: &str = include_str!("asm_files/fixtures/count.asm");
+    const COUNT_OBJ: &[u8] = include_bytes!("asm_files/fixtures/count.obj");
+    const COUNT_SYM: &str = include_str!("asm_files/fixtures/count.sym");
+
+    #[test]
+    pub fn standard_object_format() {
+        let mut asm = Assembler::new("count.asm");
+        asm.load_str(COUNT_ASM);
+        let img = asm.assemble(vec![]).unwrap();
+        assert_eq!(image_to_bytes(&img, Endian::Big).unwrap(), COUNT_OBJ);
+
+        let read = image_from_bytes(COUNT_OBJ, Endian::Big).unwrap();
+        assert_eq!(read.origin, 0x3000);
+        assert_eq!(read.data.len(), 9);
+        assert_eq!((read.data[0], read.data[8]), (0x2206, 0x0030));
+    }
+
+    #[test]
+    pub fn pennsim_symbol_tables() {
+        let mut asm = Assembler::new("count.asm");
+        asm.load_str(COUNT_ASM);
+        let img = asm.assemble(vec![]).unwrap();
+        assert_eq!(to_pennsim_symbols(&img.symbol_table), COUNT_SYM);
+
+        let symbols = parse_pennsim_symbols(COUNT_SYM, 0x3000).unwrap();
+        let symbols: Vec<(&str, u16, u16)> = symbols.iter().map(|s| (s.name.as_str(), s.rel_addr, s.abs_addr)).collect();
+        assert_eq!(symbols, vec![("LOOP", 2, 0x3002), ("COUNT", 7, 0x3007), ("ASCII", 8, 0x3008)]);
+
+        assert!(parse_pennsim_symbols(".SYM 2\n", 0x3000).is_err());
+        assert!(parse_pennsim_symbols("// Symbol table\n//\tLOOP  30G2\n", 0x3000).is_err());
+        assert!(parse_pennsim_symbols("// Symbol table\nLOOP 3002\n", 0x3000).is_err());
+    }
+
+    #[test]
+    pub fn endianness() {
+        let mut asm = Assembler::new("count.asm");
+        asm.load_str(COUNT_ASM);
+        let img = asm.assemble(vec![]).unwrap();
+        let little = image_to_bytes(&img, Endian::Little).unwrap();
+        assert_eq!(&little[..4], &[0x00, 0x30, 0x06, 0x22]);
+
+        let big = image_from_bytes(COUNT_OBJ, Endian::Big).unwrap();
+        let read = image_from_bytes(&little, Endian::Little).unwrap();
+        assert_eq!((read.origin, &read.data), (big.origin, &big.data));
+
+        assert_eq!(Endian::detect(COUNT_OBJ), Endian::Big);
+        assert_eq!(Endian::detect(&little), Endian::Little);
+        // x3030 reads as x3030 either way, so the HALT decides
+        assert_eq!(Endian::detect(&[0x30, 0x30, 0x25, 0xF0]), Endian::Little);
+        assert_eq!(Endian::detect(&[0x30, 0x30, 0xF0, 0x25]), Endian::Big);
+        // A little-endian origin of x0030 is not in user space
+        assert_eq!(Endian::detect(&[0x30, 0x00, 0x00, 0x00]), Endian::Big);
+        assert_eq!(Endian::detect(&[]), Endian::Big);
+    }
+
+    #[test]
+    pub fn headerless_image_with_magic_bytes() {
+        let mut asm = Assembler::new("magic.asm");
+        // Long enough to hold a header, so only the CRC tells them apart
+        asm.load_str(".ORIG x7F4C\n.FILL x4333\n.BLKW 15\n.END");
+        let bytes = image_to_bytes(&asm.assemble(vec![]).unwrap(), Endian::Big).unwrap();
+        assert_eq!(&bytes[..4], b"\x7FLC3");
+        let (header, img) = parse_object(&bytes, None).unwrap();
+        assert!(header.is_none());
+        assert_eq!((img.origin, img.data.len(), img.data[0]), (0x7F4C, 16, 0x4333));
+    }
+
+    #[test]
+    pub fn object_header() {
+        let with_header = |payload: &[u8], endian| {
+            let mut bytes = ObjectHeader::new(0x3002, payload, endian).to_bytes();
+            bytes.extend(payload);
+            bytes
+        };
+
+        let bytes = with_header(COUNT_OBJ, Endian::Big);
+        assert_eq!(&bytes[..4], b"\x7FLC3");
+        let (header, img) = parse_object(&bytes, None).unwrap();
+        let header = header.unwrap();
+        assert_eq!((header.version, header.entry, header.payload_words), (1, 0x3002, 10));
+        assert_eq!((img.origin, img.data.len()), (0x3000, 9));
+
+        // The header gives the byte order, whatever the caller asks for
+        let mut asm = Assembler::new("count.asm");
+        asm.load_str(COUNT_ASM);
+        let little = image_to_bytes(&asm.assemble(vec![]).unwrap(), Endian::Little).unwrap();
+        let (header, img) = parse_object(&with_header(&little, Endian::Little), Some(Endian::Big)).unwrap();
+        assert_eq!(header.unwrap().endian, Endian::Little);
+        assert_eq!((img.origin, img.data[0]), (0x3000, 0x2206));
+
+        // Headerless images still load
+        assert!(parse_object(COUNT_OBJ, None).unwrap().0.is_none());
+
+        // Without a matching CRC the magic bytes are just the start of a headerless image
+        let mut corrupted = bytes.clone();
+        corrupted[40] ^= 1;
+        let (header, img) = parse_object(&corrupted, Some(Endian::Big)).unwrap();
+        assert!(header.is_none());
+        assert_eq!((img.origin, img.data[0]), (0x7F4C, 0x4333));
+        assert!(parse_object(&bytes[..bytes.len() - 2], None).unwrap().0.is_none());
+        assert!(parse_object(&bytes[..20], None).unwrap().0.is_none());
+        let mut later = bytes.clone();
+        later[5] = 2;
+        assert!(matches!(parse_object(&later, None), Err(error::FileLoadError::InvalidHeader)));
+        // Entry point outside of the image
+        let mut bytes = ObjectHeader::new(0x4000, COUNT_OBJ, Endian::Big).to_bytes();
+        bytes.extend(COUNT_OBJ);
+        assert!(matches!(parse_object(&bytes, None), Err(error::FileLoadError::InvalidHeader)));
+    }
+}
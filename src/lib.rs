@@ -3,12 +3,13 @@ pub mod binary_utils;
 pub mod error;
 pub mod file_io;
 pub mod virtual_machine;
+pub mod console_backend;
+pub mod device_bus;
 pub mod cli;
-use std::{process::Output, thread, time};
+use std::process::Output;
 
 use assemble::*;
 use assembler::*;
-use binary_utils::*;
 use file_io::*;
 use virtual_machine::*;
 use error::CliError;
@@ -19,7 +20,14 @@ pub const TRAP_DIR_PATH: &str = "trap/";
 pub struct AssemblerFlags {
     pub case_insensitive_labels: bool,
     pub output_symbol_file: bool,
+    pub output_listing: bool,
+    pub relocatable: bool,
     pub verbose_log: bool,
+    pub dialect: Dialect,
+    pub output_format: ImageFormat,
+    pub endian: Endian,
+    pub object_header: bool,
+    pub entry: Option<String>,
 }
 
 impl AssemblerFlags {
@@ -27,7 +35,14 @@ impl AssemblerFlags {
         AssemblerFlags {
             case_insensitive_labels: true,
             output_symbol_file: true,
+            output_listing: false,
+            relocatable: false,
             verbose_log: false,
+            dialect: Dialect::Extended,
+            output_format: ImageFormat::Binary,
+            endian: Endian::Big,
+            object_header: false,
+            entry: None,
         }
     }
 
@@ -41,10 +56,47 @@ impl AssemblerFlags {
         self
     }
 
+    pub fn set_listing(&mut self, flag: bool) -> &mut Self {
+        self.output_listing = flag;
+        self
+    }
+
+    pub fn set_relocatable(&mut self, flag: bool) -> &mut Self {
+        self.relocatable = flag;
+        self
+    }
+
     pub fn set_verbose_log(&mut self, flag: bool) -> &mut Self {
         self.verbose_log = flag;
         self
     }
+
+    pub fn set_dialect(&mut self, dialect: Dialect) -> &mut Self {
+        self.dialect = dialect;
+        self
+    }
+
+    pub fn set_output_format(&mut self, format: ImageFormat) -> &mut Self {
+        self.output_format = format;
+        self
+    }
+
+    pub fn set_endian(&mut self, endian: Endian) -> &mut Self {
+        self.endian = endian;
+        self
+    }
+
+    pub fn set_object_header(&mut self, flag: bool) -> &mut Self {
+        self.object_header = flag;
+        self
+    }
+
+    /// A label or address (e.g. `x3010`); a `.obj` file then gets a header to hold it.
+    pub fn set_entry(&mut self, entry: &str) -> &mut Self {
+        self.entry = Some(entry.to_string());
+        self.object_header = true;
+        self
+    }
 }
 
 pub fn parse_arguments(args: Vec<String>) -> Result<(), CliError> {
@@ -56,7 +108,7 @@ pub fn parse_arguments(args: Vec<String>) -> Result<(), CliError> {
 
     match args[1].to_ascii_lowercase().as_str() {
         "asm" => {
-            let src_files = vec![args[2].clone()];
+            let src_files = [args[2].clone()];
             let src_file0 = src_files[0].clone(); //Guaranteed to exist
             let mut flags = AssemblerFlags::new();
 
@@ -76,6 +128,46 @@ pub fn parse_arguments(args: Vec<String>) -> Result<(), CliError> {
                             flags.set_verbose_log(true);
                         }
 
+                        "--listing" => {
+                            flags.set_listing(true);
+                        }
+
+                        "--relocatable" => {
+                            flags.set_relocatable(true);
+                        }
+
+                        "--strict" => {
+                            flags.set_dialect(Dialect::Strict);
+                        }
+
+                        arg if arg.starts_with("--dialect=") => {
+                            let name = arg.trim_start_matches("--dialect=");
+                            match Dialect::from_name(name) {
+                                Some(dialect) => flags.set_dialect(dialect),
+                                None => return Err(CliError::new(&format!("Unknown dialect '{name}', expected 'extended', 'strict' or 'compat'"))),
+                            };
+                        }
+
+                        arg if arg.starts_with("--format=") => {
+                            let name = arg.trim_start_matches("--format=");
+                            match ImageFormat::from_name(name) {
+                                Some(format) => flags.set_output_format(format),
+                                None => return Err(CliError::new(&format!("Unknown output format '{name}', expected 'obj', 'ihex' or 'srec'"))),
+                            };
+                        }
+
+                        arg if arg.starts_with("--endian=") => {
+                            flags.set_endian(parse_endian(arg)?);
+                        }
+
+                        "--header" => {
+                            flags.set_object_header(true);
+                        }
+
+                        arg if arg.starts_with("--entry=") => {
+                            flags.set_entry(arg.trim_start_matches("--entry="));
+                        }
+
                         "--link" => {
                             if arg_no+1 == args.len() {
                                 return Err(CliError::new("Expected files to link after '--link'"))
@@ -94,16 +186,77 @@ pub fn parse_arguments(args: Vec<String>) -> Result<(), CliError> {
                 Some(stripped) => stripped.to_string(),
             };
 
-            output_file0.push_str(".obj");
+            output_file0.push('.');
+            output_file0.push_str(flags.output_format.extension());
+
+            if flags.relocatable && !external_files.is_empty() {
+                return Err(CliError::new("'--relocatable' leaves imports to the linker, it cannot be used with '--link'"));
+            }
 
             cli_assemble(src_file0, output_file0, external_files, flags);
         }
-        "link" => {}
+        "fmt" => {
+            let check = match args.get(3).map(|arg| arg.as_str()) {
+                None => false,
+                Some("--check") => true,
+                Some(arg) => return Err(CliError::new(&format!("Unexpected argument '{arg}' after 'fmt', expected '--check'"))),
+            };
+            cli_format(&args[2], check)?;
+        }
+        "link" => {
+            let mut object_files: Vec<&str> = vec![];
+            let mut output_file = None;
+            let mut script_file = None;
+            let mut header = false;
+            let mut gc = false;
+            let mut keep = vec![];
+            let mut arg_no = 2;
+            while arg_no < args.len() {
+                match args[arg_no].as_str() {
+                    "-o" => match args.get(arg_no + 1) {
+                        Some(file) => {
+                            output_file = Some(file.as_str());
+                            arg_no += 1;
+                        }
+                        None => return Err(CliError::new("Expected output file name after '-o'")),
+                    },
+                    "--script" => match args.get(arg_no + 1) {
+                        Some(file) => {
+                            script_file = Some(file.as_str());
+                            arg_no += 1;
+                        }
+                        None => return Err(CliError::new("Expected linker script after '--script'")),
+                    },
+                    "--header" => header = true,
+                    "--gc" => gc = true,
+                    "--keep" => match args.get(arg_no + 1) {
+                        Some(symbol) => {
+                            keep.push(symbol.clone());
+                            gc = true;
+                            arg_no += 1;
+                        }
+                        None => return Err(CliError::new("Expected symbol name after '--keep'")),
+                    },
+                    file => object_files.push(file),
+                }
+                arg_no += 1;
+            }
+
+            match output_file {
+                Some(output_file) => cli_link(object_files, output_file, script_file, header, gc.then_some(keep))?,
+                None => return Err(CliError::new("Expected '-o' and an output file name after the files to link")),
+            }
+        }
         "load" => {
+            let mut endian = None;
+            for arg in args.iter().filter(|arg| arg.starts_with("--endian=")) {
+                endian = Some(parse_endian(arg)?);
+            }
+
             let mut src_files: Vec<&String> = args
                 .iter()
                 .enumerate()
-                .filter(|(i, val)| *i > 1)
+                .filter(|(i, val)| *i > 1 && !val.starts_with("--endian="))
                 .map(|(i, val)| val)
                 .collect();
 
@@ -113,14 +266,96 @@ pub fn parse_arguments(args: Vec<String>) -> Result<(), CliError> {
 
             let link_files = src_files.split_off(1);
 
-            cli_link_load_and_execute(src_files[0], link_files, None);
+            cli_link_load_and_execute(src_files[0], link_files, endian, None);
+        }
+        "ar" => {
+            let object_files: Vec<&str> = args[3..].iter().map(|arg| arg.as_str()).collect();
+            if object_files.is_empty() {
+                return Err(CliError::new("Expected object files to archive after the archive name"));
+            }
+            cli_archive(&args[2], object_files)?;
+        }
+        "objdump" => {
+            let mut sections = (false, false, false);
+            let mut range = None;
+            let mut endian = None;
+            for arg in &args[3..] {
+                match arg.as_str() {
+                    "--headers" => sections.0 = true,
+                    "--symbols" => sections.1 = true,
+                    arg if arg.starts_with("--range=") => {
+                        let text = arg.trim_start_matches("--range=");
+                        match objdump::parse_range(text) {
+                            Some(parsed) => range = Some(parsed),
+                            None => return Err(CliError::new(&format!("Invalid address range '{text}', expected e.g. 'x3000-x30FF'"))),
+                        }
+                        sections.2 = true;
+                    }
+                    arg if arg.starts_with("--endian=") => endian = Some(parse_endian(arg)?),
+                    _ => return Err(CliError::new(&format!("Unexpected argument '{arg}' after 'objdump', expected '--headers', '--symbols' or '--range='"))),
+                }
+            }
+            // Everything, unless only some sections were asked for
+            if sections == (false, false, false) {
+                sections = (true, true, true);
+            }
+            cli_objdump(&args[2], sections, range, endian)?;
         }
+        "diff" => {
+            let mut files: Vec<&str> = vec![];
+            let mut symbols = false;
+            let mut endian = None;
+            for arg in &args[2..] {
+                match arg.as_str() {
+                    "--sym" => symbols = true,
+                    arg if arg.starts_with("--endian=") => endian = Some(parse_endian(arg)?),
+                    arg if arg.starts_with("--") => return Err(CliError::new(&format!("Unexpected argument '{arg}' after 'diff', expected '--sym' or '--endian='"))),
+                    file => files.push(file),
+                }
+            }
+            match files.as_slice() {
+                [first, second] => cli_diff(first, second, symbols, endian)?,
+                _ => return Err(CliError::new("Expected two object files to compare after 'diff'")),
+            }
+        }
+        "sym" => match (args[2].as_str(), args.get(3)) {
+            ("export", Some(object_file)) => cli_export_symbols(object_file)?,
+            ("import", Some(object_file)) => cli_import_symbols(object_file)?,
+            _ => return Err(CliError::new("Expected 'sym export <file>.obj' or 'sym import <file>.obj'")),
+        },
         "help" => {}
         _ => return Err(CliError::new(&format!("Invalid argument '{}'", args[1]))),
     }
     Ok(())
 }
 
+/// The address of `entry`, a label or an address such as `x3010`, if it is within `img`.
+fn resolve_entry(img: &ExecutableImageOut, entry: &str, case_insensitive: bool) -> Option<u16> {
+    let label = img.symbol_table.iter().find(|symbol| {
+        !matches!(symbol.status, SymbolStatus::Import)
+            && (symbol.name == entry || (case_insensitive && symbol.name.eq_ignore_ascii_case(entry)))
+    });
+    let addr = match label {
+        Some(symbol) => symbol.abs_addr,
+        None => u16::from_str_radix(entry.strip_prefix(['x', 'X'])?, 16).ok()?,
+    };
+    let in_image = img
+        .instructions
+        .iter()
+        .chain(img.data.iter())
+        .any(|write| img.origin.wrapping_add(write.rel_addr) == addr);
+    in_image.then_some(addr)
+}
+
+/// `--endian=big` or `--endian=little`.
+fn parse_endian(arg: &str) -> Result<Endian, CliError> {
+    let name = arg.trim_start_matches("--endian=");
+    match Endian::from_name(name) {
+        Some(endian) => Ok(endian),
+        None => Err(CliError::new(&format!("Unknown byte order '{name}', expected 'big' or 'little'"))),
+    }
+}
+
 pub fn cli_assemble(
     src_file: String,
     output_file: String,
@@ -130,11 +365,13 @@ pub fn cli_assemble(
     let mut asm = Assembler::new(&src_file);
     asm.ignore_case_for_labels(flags.case_insensitive_labels);
     asm.verbose_log = flags.verbose_log;
+    asm.dialect = flags.dialect;
+    asm.relocatable = flags.relocatable;
     asm.load();
 
     //let result = asm.assemble();
 
-    let img = match asm.assemble(external_files) {
+    let mut img = match asm.assemble(external_files) {
         Ok(img) => img,
         Err(errors) => {
             error::AsmblrErr::display(&src_file, &asm.raw_lines, &errors);
@@ -143,10 +380,33 @@ pub fn cli_assemble(
         }
     };
 
-    let _ = match file_io::write_binary_to_file(&format!("{}", output_file), &img) {
+    if let Some(entry) = &flags.entry {
+        img.entry = match resolve_entry(&img, entry, flags.case_insensitive_labels) {
+            Some(addr) => Some(addr),
+            None => {
+                eprintln!("\n[ASM]\tEntry point '{entry}' is not a label or an address within the program.");
+                return;
+            }
+        };
+    }
+
+    match file_io::write_image_to_file(&output_file, &img, flags.output_format, flags.endian, flags.object_header) {
         Ok(size) => println!("[OK]\tWrote {size} bytes to {}", output_file),
         Err(e) => panic!("[FAIL]\t{:?}", e),
-    };
+    }
+
+    if flags.output_listing {
+        match file_io::write_listing_to_file(&format!("{output_file}.lst"), &img) {
+            Ok(size) => println!("[OK]\tWrote {size} listing lines to {output_file}.lst"),
+            Err(e) => panic!("[FAIL]\t{:?}", e),
+        }
+    }
+
+    // Linking and relocating the image need these, with or without a symbol file
+    match file_io::write_relocations_to_file(&format!("{output_file}.rel"), &img) {
+        Ok(count) => println!("[OK]\tWrote {count} relocations to {output_file}.rel"),
+        Err(e) => panic!("[FAIL]\t{:?}", e),
+    }
 
     if flags.output_symbol_file {
         let _ = match file_io::write_symbols_to_file(&format!("{output_file}.sym"), &img) {
@@ -166,7 +426,7 @@ pub fn cli_assemble(
                     "{:>3}\texported symbol(s).",
                     img.symbol_table
                         .iter()
-                        .filter(|s| matches!(s.status, SymbolStatus::Export))
+                        .filter(|s| s.status.is_export())
                         .count()
                 );
                 println!(
@@ -182,42 +442,481 @@ pub fn cli_assemble(
     }
 }
 
-pub fn cli_link_load_and_execute(src_file: &str, link_files: Vec<&String>, vm_flags: Option<bool>) {
+/// Formats `src_file` in place, or with `check` set, fails if it is not already formatted.
+pub fn cli_format(src_file: &str, check: bool) -> Result<(), CliError> {
+    let raw_lines = match file_io::read_lines_from_file(src_file) {
+        Ok(lines) => lines,
+        Err(e) => return Err(CliError::new(&format!("Unable to read '{src_file}', {e:?}"))),
+    };
+
+    let formatted = match formatter::format_source(&raw_lines) {
+        Ok(lines) => lines,
+        Err(errors) => {
+            error::AsmblrErr::display(src_file, &raw_lines, &errors);
+            return Err(CliError::new(&format!("Unable to format '{src_file}', {} error(s).", errors.len())));
+        }
+    };
+
+    if formatted == raw_lines {
+        println!("[OK]\t'{src_file}' is formatted.");
+        return Ok(());
+    }
+
+    if check {
+        for (i, (before, after)) in raw_lines.iter().zip(&formatted).enumerate() {
+            if before != after {
+                println!("{:02}-|{before}\n{:02}+|{after}", i + 1, i + 1);
+            }
+        }
+        return Err(CliError::new(&format!("'{src_file}' would be reformatted.")));
+    }
+
+    match file_io::write_lines_to_file(src_file, &formatted) {
+        Ok(_) => println!("[OK]\tFormatted '{src_file}'."),
+        Err(e) => return Err(CliError::new(&format!("Unable to write '{src_file}', {e:?}"))),
+    }
+    Ok(())
+}
+
+/// Links `object_files` into `output_file`, placed according to `script_file` if given, and writes
+/// the combined `output_file.sym` and the memory map `output_file.map`. With `gc`, routines and
+/// data which are unreachable from the entry point, the exports of the first file and the symbols
+/// listed in `gc` are removed first.
+pub fn cli_link(
+    object_files: Vec<&str>,
+    output_file: &str,
+    script_file: Option<&str>,
+    header: bool,
+    gc: Option<Vec<String>>,
+) -> Result<(), CliError> {
+    let script = match script_file {
+        None => linker_script::LinkerScript::default(),
+        Some(script_file) => {
+            let lines = match file_io::read_lines_from_file(script_file) {
+                Ok(lines) => lines,
+                Err(e) => return Err(CliError::new(&format!("Unable to read '{script_file}', {e:?}"))),
+            };
+            match linker_script::LinkerScript::parse(&lines) {
+                Ok(script) => script,
+                Err(errors) => {
+                    error::AsmblrErr::display(script_file, &lines, &errors);
+                    return Err(CliError::new(&format!("Invalid linker script '{script_file}'")));
+                }
+            }
+        }
+    };
+
+    let mut modules = Vec::new();
+    for path in &object_files {
+        match linker::ObjectModule::load(path) {
+            Ok(module) => modules.push(module),
+            Err(e) => {
+                error::AsmblrErr::display(path, &vec![], &vec![e]);
+                return Err(CliError::new(&format!("Unable to load '{path}' for linking")));
+            }
+        }
+    }
+
+    if let Some(keep) = gc {
+        match dead_code::eliminate_dead_code(&mut modules, &keep) {
+            Ok(report) => {
+                for line in report {
+                    println!("[GC]\t{line}");
+                }
+            }
+            Err(errors) => {
+                error::AsmblrErr::display(output_file, &vec![], &errors);
+                return Err(CliError::new(&format!("Linking failed, {} error(s).", errors.len())));
+            }
+        }
+    }
+
+    if let Err(errors) = script.place(&mut modules) {
+        error::AsmblrErr::display(output_file, &vec![], &errors);
+        return Err(CliError::new(&format!("Linking failed, {} error(s).", errors.len())));
+    }
+    let map = script.map(&modules);
+
+    let img = match linker::link(output_file, modules) {
+        Ok(img) => img,
+        Err(errors) => {
+            error::AsmblrErr::display(output_file, &vec![], &errors);
+            return Err(CliError::new(&format!("Linking failed, {} error(s).", errors.len())));
+        }
+    };
+
+    let format = ImageFormat::from_path(output_file).unwrap_or(ImageFormat::Binary);
+    // Only a header can tell where to start when the first object file is not the lowest
+    let header = header || img.entry.is_some();
+    match file_io::write_image_to_file(output_file, &img, format, Endian::Big, header) {
+        Ok(size) => println!("[OK]\tWrote {size} bytes to {output_file}"),
+        Err(e) => return Err(CliError::new(&format!("Unable to write '{output_file}', {e:?}"))),
+    }
+    match file_io::write_symbols_to_file(&format!("{output_file}.sym"), &img) {
+        Ok(count) => println!("[OK]\tWrote {count} symbols to {output_file}.sym"),
+        Err(e) => return Err(CliError::new(&format!("Unable to write '{output_file}.sym', {e:?}"))),
+    }
+    match file_io::write_lines_to_file(&format!("{output_file}.map"), &map) {
+        Ok(_) => println!("[OK]\tWrote memory map to {output_file}.map"),
+        Err(e) => return Err(CliError::new(&format!("Unable to write '{output_file}.map', {e:?}"))),
+    }
+    Ok(())
+}
+
+/// Writes `object_files`, with their symbols and relocations, into the archive `archive_file`.
+pub fn cli_archive(archive_file: &str, object_files: Vec<&str>) -> Result<(), CliError> {
+    let archive = match archive::Archive::from_objects(&object_files) {
+        Ok(archive) => archive,
+        Err((file, e)) => return Err(CliError::new(&format!("Unable to read '{file}', {e:?}"))),
+    };
+    match archive.write(archive_file) {
+        Ok(size) => println!(
+            "[OK]\tWrote {} members ({} exported symbols, {size} bytes) to {archive_file}",
+            archive.members.len(),
+            archive.index.len()
+        ),
+        Err(e) => return Err(CliError::new(&format!("Unable to write '{archive_file}', {e:?}"))),
+    }
+    Ok(())
+}
+
+/// Prints the `(headers, symbols, disassembly)` of `object_file` which are set in `sections`.
+pub fn cli_objdump(
+    object_file: &str,
+    sections: (bool, bool, bool),
+    range: Option<(u16, u16)>,
+    endian: Option<Endian>,
+) -> Result<(), CliError> {
+    let segments = match read_image_segments_from_file(object_file, endian) {
+        Ok(segments) => segments,
+        Err(e) => return Err(CliError::new(&format!("Unable to read '{object_file}', {e:?}"))),
+    };
+    let sym_file = format!("{object_file}.sym");
+    let symbols = read_symbols_beside(object_file)?;
+
+    let (headers, symbol_table, disassembly) = sections;
+    let mut lines = Vec::new();
+    if headers {
+        let bytes = read_bytes_from_file(object_file).unwrap_or_default();
+        let header = match ObjectHeader::parse(&bytes) {
+            Ok(Some((header, _))) => Some(header),
+            _ => None,
+        };
+        lines.extend(objdump::headers(object_file, &segments, header.as_ref()));
+    }
+    if symbol_table {
+        lines.push(String::new());
+        match symbols.is_empty() {
+            true => lines.push(format!("No symbols ({sym_file} not found).")),
+            false => lines.extend(objdump::symbols(&symbols)),
+        }
+    }
+    if disassembly {
+        lines.push(String::new());
+        lines.extend(objdump::disassemble(&segments, &symbols, range));
+    }
+    for line in lines.iter().skip_while(|line| line.is_empty()) {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+/// Splits `prog.obj@x5000` into the file and the address to load it at. Only a suffix of `@x`
+/// and hex digits is an address, so `a@b/prog.obj` is just a path.
+fn split_load_address(file: &str) -> Result<(&str, Option<u16>), String> {
+    let Some((path, hex)) = file.rsplit_once('@').and_then(|(path, addr)| {
+        let hex = addr.strip_prefix(['x', 'X'])?;
+        (!hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit())).then_some((path, hex))
+    }) else {
+        return Ok((file, None));
+    };
+    match u16::from_str_radix(hex, 16) {
+        Ok(addr) => Ok((path, Some(addr))),
+        Err(_) => Err(format!("Invalid load address 'x{hex}' for '{path}', expected e.g. '{path}@x5000'")),
+    }
+}
+
+/// The symbols in `<file>.obj.sym`, or none if there is no such file.
+fn read_symbols_beside(object_file: &str) -> Result<Vec<Symbol>, CliError> {
+    let sym_file = format!("{object_file}.sym");
+    match std::path::Path::new(&sym_file).exists() {
+        true => match read_symbols_from_file(&sym_file) {
+            Ok(symbols) => Ok(symbols),
+            Err(e) => Err(CliError::new(&format!("Unable to read '{sym_file}', {e:?}"))),
+        },
+        false => Ok(vec![]),
+    }
+}
+
+/// Prints the words which differ between `first` and `second`, side by side, and a summary which
+/// includes their symbols if `symbols` is set. Fails if they differ, like `diff`.
+pub fn cli_diff(first: &str, second: &str, symbols: bool, endian: Option<Endian>) -> Result<(), CliError> {
+    let mut images = Vec::new();
+    for object_file in [first, second] {
+        match read_image_segments_from_file(object_file, endian) {
+            Ok(segments) => images.push(SparseImage::from_segments(&segments)),
+            Err(e) => return Err(CliError::new(&format!("Unable to read '{object_file}', {e:?}"))),
+        }
+    }
+    let (first_symbols, second_symbols) = (read_symbols_beside(first)?, read_symbols_beside(second)?);
+
+    let diff = match symbols {
+        true => diff::ImageDiff::compare(&images[0], &images[1], &first_symbols, &second_symbols),
+        false => diff::ImageDiff::compare(&images[0], &images[1], &[], &[]),
+    };
+    for line in diff.lines((first, second), (&first_symbols, &second_symbols)) {
+        println!("{line}");
+    }
+    match diff.is_empty() {
+        true => Ok(()),
+        false => Err(CliError::new(&format!("'{first}' and '{second}' differ."))),
+    }
+}
+
+/// The symbol table PennSim and lc3tools read beside `prog.obj`: `prog.sym`.
+fn pennsim_symbols_path(object_file: &str) -> String {
+    let stem = object_file.strip_suffix(".obj").unwrap_or(object_file);
+    format!("{stem}.sym")
+}
+
+/// Writes the symbols of `object_file` (from `<file>.obj.sym`) to `<file>.sym` for PennSim and
+/// lc3tools.
+pub fn cli_export_symbols(object_file: &str) -> Result<(), CliError> {
+    let sym_file = format!("{object_file}.sym");
+    let symbols = match read_symbols_from_file(&sym_file) {
+        Ok(symbols) => symbols,
+        Err(e) => return Err(CliError::new(&format!("Unable to read '{sym_file}', {e:?}"))),
+    };
+    let pennsim_file = pennsim_symbols_path(object_file);
+    match write_bytes_to_file(&pennsim_file, to_pennsim_symbols(&symbols).as_bytes()) {
+        Ok(_) => println!("[OK]\tWrote {} symbols to {pennsim_file}", symbols.len()),
+        Err(e) => return Err(CliError::new(&format!("Unable to write '{pennsim_file}', {e:?}"))),
+    }
+    Ok(())
+}
+
+/// Reads the symbols PennSim or lc3tools wrote beside `object_file` (`<file>.sym`) into
+/// `<file>.obj.sym`, so the object file can be loaded and linked.
+pub fn cli_import_symbols(object_file: &str) -> Result<(), CliError> {
+    let img = match read_exectuable_img_from_file(object_file, None) {
+        Ok(img) => img,
+        Err(e) => return Err(CliError::new(&format!("Unable to read '{object_file}', {e:?}"))),
+    };
+    let pennsim_file = pennsim_symbols_path(object_file);
+    let symbols = match read_bytes_from_file(&pennsim_file).map(|bytes| String::from_utf8_lossy(&bytes).to_string()) {
+        Ok(contents) => match parse_pennsim_symbols(&contents, img.origin) {
+            Ok(symbols) => symbols,
+            Err(e) => return Err(CliError::new(&format!("Invalid symbol table '{pennsim_file}', {e:?}"))),
+        },
+        Err(e) => return Err(CliError::new(&format!("Unable to read '{pennsim_file}', {e:?}"))),
+    };
+
+    let mut out = ExecutableImageOut::new(object_file.to_string());
+    out.origin = img.origin;
+    out.symbol_table = symbols;
+    let sym_file = format!("{object_file}.sym");
+    match write_symbols_to_file(&sym_file, &out) {
+        Ok(count) => println!("[OK]\tWrote {count} symbols to {sym_file}"),
+        Err(e) => return Err(CliError::new(&format!("Unable to write '{sym_file}', {e:?}"))),
+    }
+    Ok(())
+}
+
+/// Reads `src_file` and `link_files`, with the members of `archive_files` which they need.
+fn modules_with_archives(
+    src_file: &str,
+    link_files: &[&String],
+    archive_files: &[&String],
+) -> Result<Vec<linker::ObjectModule>, Vec<error::AsmblrErr>> {
+    let mut modules = Vec::new();
+    for path in [src_file].into_iter().chain(link_files.iter().map(|f| f.as_str())) {
+        modules.push(linker::ObjectModule::load(path).map_err(|e| vec![e])?);
+    }
+
+    let mut archives = Vec::new();
+    for path in archive_files {
+        match archive::Archive::read(path) {
+            Ok(archive) => archives.push((path.to_string(), archive)),
+            Err(e) => {
+                let msg = format!("Unable to read archive '{path}', {e:?}");
+                return Err(vec![error::AsmblrErr::new(None, msg).link_error().clone()]);
+            }
+        }
+    }
+    linker::pull_archive_members(&mut modules, &archives).map_err(|e| vec![e])?;
+    for module in &modules[1 + link_files.len()..] {
+        println!("[LINK]\tPulled in {}", module.name);
+    }
+    Ok(modules)
+}
+
+/// Reports the imports in `tables` which none of them export, or which don't match the export,
+/// returning false if there are any.
+fn check_imports(src_file: &str, tables: &[(&str, &[Symbol])]) -> bool {
+    let missing = linker::missing_imports(tables);
+    if !missing.is_empty() {
+        eprintln!("Error linking executable images, {} unresolved import(s):", missing.len());
+        for (symbol, module) in missing {
+            eprintln!("\t'{symbol}', imported by '{module}'");
+        }
+        eprintln!("Load the object files which export them, e.g. 'lc3-asm-vm load {src_file} lib.obj'.");
+        return false;
+    }
+    let mismatched = linker::mismatched_imports(tables);
+    if !mismatched.is_empty() {
+        eprintln!("Error linking executable images, {} mismatched import(s):", mismatched.len());
+        for msg in mismatched {
+            eprintln!("\t{msg}");
+        }
+        return false;
+    }
+    true
+}
+
+pub fn cli_link_load_and_execute(src_file: &str, link_files: Vec<&String>, endian: Option<Endian>, vm_flags: Option<bool>) {
     // Set up VM context
     let verbose_log = false;
     
 
     //Load trap files according to config
+    let read_x20 = TrapInstruction::new(TRAP_DIR_PATH, "read", 0x20);
     let putc_x21 = TrapInstruction::new(TRAP_DIR_PATH, "putc", 0x21);
     let puts_x22 = TrapInstruction::new(TRAP_DIR_PATH, "puts", 0x22);
     let getc_x23 = TrapInstruction::new(TRAP_DIR_PATH, "getc", 0x23);
     let halt_x25 = TrapInstruction::new(TRAP_DIR_PATH, "halt", 0x25);
 
-    let mut trap_instructions = vec![putc_x21, puts_x22, getc_x23, halt_x25];
+    let mut trap_instructions = vec![read_x20, putc_x21, puts_x22, getc_x23, halt_x25];
 
-    //load files
-    let src_img = match read_exectuable_img_from_file(&src_file, Endian::Little){
-        Err(e) => {
-            eprintln!("Error loading executable image '{}', {e:?}", src_file);
+    // `prog.obj@x5000` loads prog.obj at x5000, rather than at its origin
+    let mut files: Vec<(&str, Option<u16>)> = Vec::new();
+    for file in [src_file].into_iter().chain(link_files.iter().map(|file| file.as_str())) {
+        match split_load_address(file) {
+            Ok(split) => files.push(split),
+            Err(msg) => {
+                eprintln!("{msg}");
+                return;
+            }
+        }
+    }
+    let src_file = files[0].0;
+
+    // Archives (.lib) only supply the members which resolve imports, so everything is linked first
+    let (archive_files, link_files): (Vec<&String>, Vec<&String>) =
+        link_files.into_iter().partition(|file| file.ends_with(".lib"));
+    let mut executable_images = Vec::new();
+    if !archive_files.is_empty() {
+        if files.iter().any(|(_, load_addr)| load_addr.is_some()) {
+            eprintln!("Error loading executable images, a load address (file@xADDR) cannot be given when linking with archives.");
             return;
         }
-        Ok(img) => img,
-    };
+        let modules = match modules_with_archives(src_file, &link_files, &archive_files) {
+            Ok(modules) => modules,
+            Err(errors) => {
+                error::AsmblrErr::display(src_file, &vec![], &errors);
+                return;
+            }
+        };
+        let tables: Vec<(&str, &[Symbol])> = modules
+            .iter()
+            .map(|module| (module.name.as_str(), module.symbols.as_slice()))
+            .collect();
+        if !check_imports(src_file, &tables) {
+            return;
+        }
+        match linker::link(src_file, modules) {
+            Ok(img) => {
+                let entry = img.entry;
+                let img = ExecutableImageIn::from_writes(img.origin, &img.data);
+                executable_images = entry_first(vec![img], entry);
+            }
+            Err(errors) => {
+                error::AsmblrErr::display(src_file, &vec![], &errors);
+                return;
+            }
+        }
+    }
 
-    let mut executable_images = vec![src_img];
+    //load files
+    if executable_images.is_empty() {
+        // .obj, Intel HEX or S-records, detected from each file; the first segment of the
+        // source file holds its entry point
+        let mut symbol_tables = Vec::new();
+        for (path, load_addr) in &files {
+            let mut segments = match read_image_segments_from_file(path, endian) {
+                Err(e) => {
+                    eprintln!("Error loading executable image '{path}', {e:?}");
+                    return;
+                }
+                Ok(segments) => segments,
+            };
+            let rel_file = format!("{path}.rel");
+            let relocations = match std::path::Path::new(&rel_file).exists() {
+                true => match read_relocations_from_file(&rel_file) {
+                    Ok(relocations) => Some(relocations),
+                    Err(e) => {
+                        eprintln!("Error loading relocations '{rel_file}', {e:?}");
+                        return;
+                    }
+                },
+                false => None,
+            };
+            let symbols = match read_symbols_from_file(&format!("{path}.sym")) {
+                Ok(symbols) => Some(symbols),
+                // Its references to other images would go unchecked
+                Err(e) if relocations.as_ref().is_some_and(|r| !r.is_empty()) => {
+                    eprintln!("Error loading executable image '{path}', it has relocations but no symbol table, {e:?}");
+                    return;
+                }
+                Err(e) => {
+                    eprintln!("[LINK]\tWARNING: unable to check the imports of '{path}', {e:?}");
+                    None
+                }
+            };
 
-    for link_file in link_files{
-        let img = match read_exectuable_img_from_file(&link_file, Endian::Little){
-            Err(e) => {
-                eprintln!("Error linking executable image '{}', {e:?}", src_file);
-                return;
+            if let Some(load_addr) = load_addr {
+                let symbols = symbols.as_deref().unwrap_or_default();
+                if let Err(msg) = linker::relocate_image(path, &mut segments, *load_addr, relocations.as_deref(), symbols) {
+                    eprintln!("Error relocating executable image, {msg}");
+                    return;
+                }
+                println!("[LOAD]\tLoaded '{path}' at x{load_addr:04x}");
             }
-            Ok(img) => img,
-        }; 
-        executable_images.push(ExecutableImageIn{
-            data: img.data,
-            origin: img.origin,
-        });
+
+            executable_images.extend(segments);
+            if let Some(symbols) = symbols {
+                symbol_tables.push((*path, symbols));
+            }
+        }
+
+        // The imports of the other images were resolved where an image was assembled, so it
+        // cannot move if they refer to it
+        for (path, _) in files.iter().filter(|(_, load_addr)| load_addr.is_some()) {
+            let Some((_, exports)) = symbol_tables.iter().find(|(table, _)| table == path) else {
+                continue;
+            };
+            for (other, symbols) in symbol_tables.iter().filter(|(table, _)| table != path) {
+                let imported = symbols.iter().find(|symbol| {
+                    matches!(symbol.status, SymbolStatus::Import)
+                        && exports.iter().any(|export| export.status.is_export() && export.name.eq_ignore_ascii_case(&symbol.name))
+                });
+                if let Some(symbol) = imported {
+                    eprintln!(
+                        "Error relocating executable image, '{other}' imports '{}' from '{path}' at its origin, so '{path}' cannot be moved.",
+                        symbol.name
+                    );
+                    return;
+                }
+            }
+        }
+
+        // Refuse to start if a loaded image imports a symbol which none of them export
+        let tables: Vec<(&str, &[Symbol])> = symbol_tables
+            .iter()
+            .map(|(path, symbols)| (*path, symbols.as_slice()))
+            .collect();
+        if !check_imports(src_file, &tables) {
+            return;
+        }
     }
 
     if  let Some(((min1, max1), (min2, max2))) = ExecutableImageIn::images_overlap(&executable_images){
@@ -237,7 +936,9 @@ pub fn cli_link_load_and_execute(src_file: &str, link_files: Vec<&String>, vm_fl
         for (addr, val) in trap.memory_writes {
             ctx.write_memory(addr, val);
         }
-        ctx.load_binary_into_memory(trap.instructions, trap.origin);
+        for (addr, val) in trap.instructions {
+            ctx.write_memory(addr, val);
+        }
     }
 
     for img in executable_images{
@@ -247,31 +948,13 @@ pub fn cli_link_load_and_execute(src_file: &str, link_files: Vec<&String>, vm_fl
         }
     }
 
-    ctx.run_io_thread();
-    thread::sleep(time::Duration::from_millis(50));
-    loop {
-        ctx.fetch();
-        ctx.decode();
-        ctx.execute(None); //IF IMPORT SYMBOL TABLE
-
-        if !flag_is_set(ctx.read_memory(ctx.mcr_address), 15) {
-            thread::sleep(time::Duration::from_millis(10));
-            print!("Ending ctx instance... ");
-            ctx.write_memory(ctx.kbsr_address, set_flag_true(0, 14));
-            ctx.write_memory(ctx.dsr_address, set_flag_true(0, 14));
-            print!("Press any key to exit...");
-            while flag_is_set(ctx.read_memory(ctx.kbsr_address), 14)
-                || flag_is_set(ctx.read_memory(ctx.dsr_address), 14)
-            {
-                //wait for Input server to terminate.
-                
-                thread::sleep(time::Duration::from_millis(10));
-            }
-
-            print!("Done.\n");
-
-            break;
-        }
-        //Term::stdout().read_char();
-    }
+    ctx.attach_console(console_backend::default_console());
+    let started = std::time::Instant::now();
+    ctx.run(None); //IF IMPORT SYMBOL TABLE
+    let elapsed = started.elapsed();
+    println!(
+        "[VM]\tExecuted {} instructions in {elapsed:.2?} ({:.0} instructions/s)",
+        ctx.instruction_count,
+        ctx.instruction_count as f64 / elapsed.as_secs_f64()
+    );
 }
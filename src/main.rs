@@ -9,6 +9,8 @@ pub mod binary_utils;
 pub mod error;
 pub mod file_io;
 pub mod virtual_machine;
+pub mod console_backend;
+pub mod device_bus;
 
 //
 //
@@ -32,7 +34,10 @@ fn main() {
     }
     match parse_arguments(args){
         Ok(_) => {},
-        Err(cli_error) => cli_error.dipsplay(),
+        Err(cli_error) => {
+            cli_error.dipsplay();
+            std::process::exit(1);
+        }
     }
 }
 
@@ -119,6 +124,8 @@ fn main_obsolete() {
         let _ = match file_io::write_binary_to_file(
             &format!("./src/obj_files/{}.obj", buffer),
             &img,
+            file_io::Endian::Big,
+            false,
         ) {
             Ok(size) => println!("[OK]\tWrote {size} bytes to./src/obj_files/{}.obj ", buffer),
             Err(e) => panic!("[FAIL]\t{:?}", e),
@@ -126,7 +133,7 @@ fn main_obsolete() {
 
         let obj = file_io::read_binary_from_file(
             &format!("./src/obj_files/{}.obj", buffer),
-            file_io::Endian::Little,
+            file_io::Endian::Big,
         )
         .expect("Unable to open multiply.obj");
         for (index, word) in obj.iter().enumerate() {
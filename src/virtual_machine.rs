@@ -1,12 +1,8 @@
 use crate::binary_utils::as_negative_i32;
-use console::Term;
+use crate::console_backend::ConsoleBackend;
+use crate::device_bus::{self, DeviceBus};
 use core::panic;
-use core::time;
 //use std::borrow::BorrowMut;
-use std::io::Write;
-use std::sync::Arc;
-use std::sync::Mutex;
-use std::thread::{self};
 
 use crate::binary_utils::{
     self, add_2s_complement, flag_is_set, instructions::*, is_negative, MAX_MEMORY, MAX_MEMORY_SIZE,
@@ -84,12 +80,6 @@ pub enum OP {
     TRAP, // execute trap
 }
 
-#[derive(Debug)]
-pub struct IORegister {
-    signal: u16,
-    data: u16,
-}
-
 #[derive(Debug)]
 pub struct VirtualMachine {
     memory: [u16; 0xFFFF],
@@ -103,8 +93,7 @@ pub struct VirtualMachine {
 
     pub mcr_address: u16,
 
-    keyboard_reg_mutex: Arc<Mutex<IORegister>>,
-    display_reg_mutex: Arc<Mutex<IORegister>>,
+    bus: DeviceBus,
 
     pub run: bool,
     pub debug_enabled: bool,
@@ -114,7 +103,7 @@ pub struct VirtualMachine {
     origin: u16,
 
     current_instruction: Instruction,
-    pub instruction_count: i32,
+    pub instruction_count: u64,
 }
 
 #[derive(Debug)]
@@ -133,19 +122,15 @@ impl VirtualMachine {
                 word: 0,
             },
 
-            kbsr_address: 0xfe00,
-            kbdr_address: 0xfe02,
+            kbsr_address: device_bus::KBSR,
+            kbdr_address: device_bus::KBDR,
 
-            dsr_address: 0xfe04,
-            ddr_address: 0xfe06,
+            dsr_address: device_bus::DSR,
+            ddr_address: device_bus::DDR,
 
             mcr_address: 0xfffe,
 
-            keyboard_reg_mutex: Arc::new(Mutex::new(IORegister { data: 0, signal: 0 })),
-            display_reg_mutex: Arc::new(Mutex::new(IORegister {
-                data: 0,
-                signal: 0x8000,
-            })),
+            bus: DeviceBus::new(),
 
             registers: Registers::new(),
 
@@ -167,136 +152,28 @@ impl VirtualMachine {
         self.origin = pc;
     }
 
-    pub fn run_io_thread(&mut self) {
-        self.memory[self.dsr_address as usize] = 0x8000;
-
-        let kb_registers = self.keyboard_reg_mutex.clone();
-        let disp_registers = self.display_reg_mutex.clone();
-        println!("[IO]\tStarting IO threads...");
-        thread::spawn(move || {
-            let mut term = Term::stdout();
-            //println!("[IO]\tStarting output server.");
-            let mut count = 0;
-            loop {
-                let mut display = loop {
-                    let lock_attempt = disp_registers.try_lock();
-                    match lock_attempt {
-                        Err(e) => {
-                            count += 1;
-                            if count > 10 {
-                                println!("[IO] Waiting for display_reg_mutex. {e}");
-                            }
-                            thread::sleep(time::Duration::from_millis(5 + count));
-                            continue;
-                        }
-
-                        Ok(lock) => {
-                            count = 0;
-                            break lock;
-                        }
-                    }
-                };
-                //println!("[IO] DSR={:016b}", display.signal);
-                if (*display).signal == 0x0000
-                /*&& (*display).data != 0*/
-                {
-                    //print!("[IO] [DISP] Output detected {}", display.data);
-                    //There is a char to read from DDR which has not been displayed yet.
-                    //(*display).signal[15 = 1;
-                    if display.data > 127 {
-                        let ch: String = String::from_utf16_lossy(&[display.data]);
-                        drop(display);
-                        panic!("[IO ERROR] Non-ascii character '{}' in DDR.", ch);
-                    }
-                    //println!("[IO]\tDisplaying {:08b} ASCII {}", binary_utils::truncate_to_bit((*display).data, 7), binary_utils::truncate_to_bit((*display).data, 7));
-
-                    match term.write(&[binary_utils::truncate_to_bit((*display).data, 7) as u8]) {
-                        Ok(_) => {
-                            (*display).signal = 0x8000;
-                        }
-                        Err(e) => {
-                            println!("[IO] Error writing to console {e:?}");
-                        }
-                    }
-                }
-
-                if binary_utils::flag_is_set((*display).signal, 14) {
-                    //Kill signal
-                    println!("Ending output server...");
+    /// Connects the keyboard and display registers to `console`.
+    pub fn attach_console(&mut self, console: Box<dyn ConsoleBackend>) {
+        self.bus.attach_console(console);
+    }
 
-                    drop(term);
-                    //println!("Ending output server...");
-                    (*display).signal = binary_utils::set_flag_false((*display).signal, 14);
-                    break;
-                }
-                drop(display);
-                thread::sleep(time::Duration::from_millis(1));
+    /// Runs the program until the machine control register is cleared (by `HALT`), ticking the
+    /// devices after each instruction. Stops early if the program waits for a key after the
+    /// console's input has ended, e.g. at the end of a piped file.
+    pub fn run(&mut self, symbol_table: Option<&crate::assemble::SymbolTable>) {
+        while flag_is_set(self.read_memory(self.mcr_address), 15) {
+            self.fetch();
+            self.decode();
+            self.execute(symbol_table);
+            if let Err(msg) = self.bus.tick() {
+                println!("[IO ERROR]\t{msg}");
+                break;
             }
-        });
-
-        let _input_server = thread::spawn(move || {
-            let term = Term::stdout();
-            let mut kill_signal = false;
-            //println!("[IO]\tStarting input server.");
-            loop {
-                // println!(
-                //     "INPUT: '{}' (ASCII {input_char})",
-                //     char::from_u32(input_char as u32).unwrap()
-                // );
-                let input_char: u16 = term
-                    .read_char()
-                    .expect("Failure to read input char.")
-                    .try_into()
-                    .expect("msg");
-
-                //let mut attempt_count = 0;
-                let mut kb_regs = loop {
-                    let lock_attempt = kb_registers.try_lock();
-                    match lock_attempt {
-                        Err(_) => {
-                            // if attempt_count > 10 {
-                            //     println!("[IO] Waiting for keyboard_register_mutex. {e}");
-
-                            // }
-                            // attempt_count += 1;
-                            // thread::sleep(time::Duration::from_millis(5 + attempt_count));
-                            continue;
-                        }
-
-                        Ok(lock) => {
-                            break lock;
-                        }
-                    }
-                };
-
-                if binary_utils::flag_is_set((*kb_regs).signal, 14) {
-                    //Kill signal
-                    term.write_line("Ending input server...").unwrap();
-                    kill_signal = true;
-                    
-                    drop(term);
-                    (*kb_regs).signal = binary_utils::set_flag_false((*kb_regs).signal, 14);
-                    return;
-                    //println!("Ending output server...");
-                    // (*kb_regs).signal = binary_utils::set_flag_false((*kb_regs).signal, 14);
-                }
-
-                if !binary_utils::flag_is_set((*kb_regs).signal, 15) {
-                    //println!("[IO]\tChar input recived. KBSR is clear. Updating KBSR and KBDR");
-                    (*kb_regs).signal = binary_utils::set_flag_true(0, 15);
-                    (*kb_regs).data = input_char;
-                } else {
-                    //println!("[IO]\tChar input recived. KBSR is NOT clear. Ignoring input.");
-                }
-
-                drop(kb_regs);
-                //println!("[IO] Dropped kb_regs.");
-                thread::sleep(time::Duration::from_millis(2));
+            if self.bus.input_ended() {
+                println!("\n[IO]\tThe program is waiting for a key, but the input has ended.");
+                break;
             }
-        });
-
-        self.write_memory(self.ddr_address, 0);
-        self.write_memory(self.dsr_address, 0);
+        }
     }
 
     pub fn load_binary_into_memory(&mut self, binary: Vec<u16>, program_start_addr: u16) {
@@ -323,98 +200,11 @@ impl VirtualMachine {
             panic!("RUNTIME ERROR: Cannot access memory out of bounds.");
         }
 
-        if address == self.kbsr_address {
-            if self.debug_enabled {
-                println!("\t\t(!)\tReading Keyboard Registers (SIGNAL)...(!)\t");
-            }
-
-            // let kb_reg = self
-            //     .keyboard_reg_mutex
-            //     .try_lock();
-            //     .expect("[CPU]\t[KBSR READ] Failed to lock Keyboard Registers.");
-            let mut attempt_count = 0;
-            let kb_reg = loop {
-                let lock_attempt = self.keyboard_reg_mutex.try_lock();
-                match lock_attempt {
-                    Err(e) => {
-                        if attempt_count > 10 {
-                            println!("[CPU] Waiting for keyboard_register_mutex. {e}");
-                        }
-                        attempt_count += 1;
-                        thread::sleep(time::Duration::from_millis(5 + attempt_count));
-                        continue;
-                    }
-
-                    Ok(lock) => {
-                        break lock;
-                    }
-                }
-            };
-            let signal = kb_reg.signal;
-            self.memory[address as usize] = signal;
-            drop(kb_reg);
-            thread::sleep(time::Duration::from_millis(10));
-        }
-
-        if address == self.kbdr_address {
-            if self.debug_enabled {
-                println!("[CPU]\t(!)Reading kbdr\t\t(!)");
-            }
-
-            let mut kb_reg = self
-                .keyboard_reg_mutex
-                .try_lock()
-                .expect("[CPU] Failed to lock kb_reg (DATA)");
-            // if binary_utils::flag_is_set(kb_reg.signal, 15){
-
-            // }
-            self.memory[address as usize] = kb_reg.data;
-            kb_reg.signal = 0;
-            drop(kb_reg);
-            //thread::sleep(time::Duration::from_millis(10));
-        }
-
-        if address == self.dsr_address {
-            /*let disp_reg = self
-            .display_reg_mutex
-            .try_lock()
-            .expect("[CPU] Failed to lock disp_reg (signal)");*/
-            //let mut attempt_count = 0;
-            let disp_reg = loop {
-                let lock_attempt = self.display_reg_mutex.try_lock();
-
-                match lock_attempt {
-                    Err(_) => {
-                        // attempt_count += 1;
-                        // if attempt_count > 10 {
-                        //     //println!("[CPU] [READ] Waiting for display_reg_mutex. {e}");
-                        // }
-
-                        //thread::sleep(time::Duration::from_millis(1 + attempt_count));
-                        continue;
-                    }
-
-                    Ok(lock) => {
-                        break lock;
-                    }
-                }
-            };
-            self.memory[address as usize] = disp_reg.signal;
+        if let Some(value) = self.bus.read(address) {
             if self.debug_enabled {
-                println!("[CPU] Reading DSR. {}", self.memory[address as usize]);
+                println!("[IO]\tReading device register x{address:04x} (={value:04x})");
             }
-        }
-
-        if address == self.ddr_address {
-            let disp_reg = self
-                .keyboard_reg_mutex
-                .try_lock()
-                .expect("[CPU] Failed to lock disp_reg (DATA)");
-
-            // if binary_utils::flag_is_set(kb_reg.signal, 15){
-
-            // }
-            self.memory[address as usize] = disp_reg.data;
+            self.memory[address as usize] = value;
         }
 
         let address: usize = address
@@ -435,41 +225,7 @@ impl VirtualMachine {
             panic!("[WRITE]\tCannot access memory out of bounds/");
         }
 
-        if address == self.kbsr_address {
-            // let mut kbsr_updater = self.kbsr_mutex.lock().unwrap();
-            // *kbsr_updater = value;
-
-            let mut kb_reg = self
-                .keyboard_reg_mutex
-                .try_lock()
-                .expect("[CPU][WRITE MEM]\tUnable to lock kb_reg.");
-            (*kb_reg).signal = value;
-        }
-
-        if address == self.ddr_address {
-            let mut attempt_count = 0;
-            let mut disp_reg = loop {
-                let lock_attempt = self.display_reg_mutex.try_lock();
-                match lock_attempt {
-                    Err(_) => {
-                        if attempt_count > 10 {
-                            //thread::sleep(time::Duration::from_millis(attempt_count-5));
-                            //println!("[CPU] [WRITE] Waiting for display_reg_mutex. {e}");
-                        }
-                        attempt_count += 1;
-
-                        continue;
-                    }
-
-                    Ok(lock) => {
-                        break lock;
-                    }
-                }
-            };
-            (*disp_reg).data = value;
-            (*disp_reg).signal = 0; //Clear DSR; not ready for another char
-            drop(disp_reg);
-        }
+        self.bus.write(address, value);
 
         let address: usize = address
             .try_into()
@@ -862,7 +618,9 @@ impl VirtualMachine {
             subroutine_addr = add_2s_complement(pcoffset11_sext, self.program_counter);
             //println!("pc_offset_11 {} + {} = {subroutine_addr}", as_negative_i32(pcoffset11_sext), self.program_counter)
         } else {
-            subroutine_addr = get_register_at(instr, (6, 8));
+            //JSRR, the base register is read before R7 is written, as it may be R7
+            let base_reg = get_register_at(instr, (6, 8));
+            subroutine_addr = self.read_reg(base_reg);
         }
 
         self.set_reg(7, self.program_counter); //Save addr of next instr
@@ -913,3 +671,154 @@ impl VirtualMachine {
         self.registers.read(register)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::assemble::Assembler;
+    use crate::console_backend::BufferConsole;
+
+    #[test]
+    pub fn console_io() {
+        // Echoes each character in upper case until '.', then halts
+        let mut asm = Assembler::new("upper.asm");
+        asm.load_str(
+            ".ORIG x3000
+            LOOP LDI R1, KBSR
+            BRzp LOOP
+            LDI R0, KBDR
+            LD R3, DOT
+            ADD R2, R0, R3
+            BRz DONE
+            LD R3, UPPER
+            ADD R0, R0, R3
+            WAIT LDI R1, DSR
+            BRzp WAIT
+            STI R0, DDR
+            BRnzp LOOP
+            DONE AND R0, R0, #0
+            STI R0, MCR
+            KBSR .FILL xFE00
+            KBDR .FILL xFE02
+            DSR .FILL xFE04
+            DDR .FILL xFE06
+            MCR .FILL xFFFE
+            DOT .FILL #-46
+            UPPER .FILL #-32
+            .END",
+        );
+        let img = asm.assemble(vec![]).unwrap();
+
+        let mut vm = VirtualMachine::new();
+        vm.set_program_origin(img.origin);
+        for w in img.instructions.iter().chain(img.data.iter()) {
+            vm.write_memory(img.origin + w.rel_addr, w.value);
+        }
+        let console = BufferConsole::new("abc.def");
+        vm.attach_console(Box::new(console.clone()));
+        vm.run(None);
+
+        assert_eq!(console.output(), "ABC");
+    }
+
+    #[test]
+    pub fn checks_for_a_key_and_continues() {
+        // Writes '!' if no key is waiting, without waiting for one
+        let mut asm = Assembler::new("poll.asm");
+        asm.load_str(
+            ".ORIG x3000
+            AND R0, R0, #0
+            LDI R1, KBSR
+            BRn DONE
+            LD R0, BANG
+            WAIT LDI R1, DSR
+            BRzp WAIT
+            STI R0, DDR
+            DONE AND R0, R0, #0
+            STI R0, MCR
+            KBSR .FILL xFE00
+            DSR .FILL xFE04
+            DDR .FILL xFE06
+            MCR .FILL xFFFE
+            BANG .FILL x21
+            .END",
+        );
+        let img = asm.assemble(vec![]).unwrap();
+
+        let mut vm = VirtualMachine::new();
+        vm.set_program_origin(img.origin);
+        for w in img.instructions.iter().chain(img.data.iter()) {
+            vm.write_memory(img.origin + w.rel_addr, w.value);
+        }
+        let console = BufferConsole::new("");
+        vm.attach_console(Box::new(console.clone()));
+        vm.run(None);
+
+        assert_eq!(console.output(), "!");
+        assert_eq!(vm.instruction_count, 9);
+    }
+
+    #[test]
+    pub fn runs_veneers_to_far_imports() {
+        let mut lib = Assembler::new("flib.asm");
+        lib.load_str(
+            ".ORIG x4000
+            PRINT .EXPORT LD R0, BANG
+            WAIT LDI R1, DSR
+            BRzp WAIT
+            STI R0, DDR
+            RET
+            DSR .FILL xFE04
+            DDR .FILL xFE06
+            BANG .FILL x21
+            .END",
+        );
+        let lib = lib.assemble(vec![]).unwrap();
+        let sym_path = std::env::temp_dir().join(format!("lc3-vm-veneer-test-{}.sym", std::process::id()));
+        let sym_path = sym_path.to_str().unwrap();
+        crate::file_io::write_symbols_to_file(sym_path, &lib).unwrap();
+
+        // PRINT is too far away for JSR, so both calls go through `LD R7, slot` and `JSRR R7`
+        let mut far = Assembler::new("far.asm");
+        far.load_str(
+            ".ORIG x3000
+            JSR PRINT
+            JSR PRINT
+            AND R0, R0, #0
+            STI R0, MCR
+            MCR .FILL xFFFE
+            PRINT .IMPORT
+            .END",
+        );
+        let far = far.assemble(vec![sym_path]);
+        std::fs::remove_file(sym_path).unwrap();
+        let far = far.unwrap();
+
+        let module = |img: crate::assemble::ExecutableImageOut| {
+            let writes: Vec<crate::assemble::MemoryWrite> = img
+                .instructions
+                .iter()
+                .chain(img.data.iter())
+                .map(|w| crate::assemble::MemoryWrite { rel_addr: w.rel_addr, value: w.value })
+                .collect();
+            crate::linker::ObjectModule {
+                name: img.name.clone(),
+                image: crate::assemble::ExecutableImageIn::from_writes(img.origin, &writes),
+                symbols: img.symbol_table,
+                relocations: img.relocations,
+            }
+        };
+        let img = crate::linker::link("far.obj", vec![module(far), module(lib)]).unwrap();
+
+        let mut vm = VirtualMachine::new();
+        vm.set_program_origin(0x3000);
+        for w in &img.data {
+            vm.write_memory(img.origin.wrapping_add(w.rel_addr), w.value);
+        }
+        let console = BufferConsole::new("");
+        vm.attach_console(Box::new(console.clone()));
+        vm.run(None);
+
+        assert_eq!(console.output(), "!!");
+    }
+}